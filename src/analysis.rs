@@ -0,0 +1,99 @@
+use crate::keyboard_loop::FPS;
+use crate::*;
+
+use std::collections::VecDeque;
+use std::mem::take;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use cpal::StreamConfig;
+
+const DECAY: f32 = 30. / FPS as f32;
+
+/// Frequencies that are considered when looking for beats
+const BASS_RANGE: Range<f32> = 20.0..150.0;
+/// How much louder than average the bass needs to be to count as a beat
+const BEAT_THRESHOLD: f32 = 1.5;
+/// Number of frames of bass energy that the average is taken over
+const BEAT_HISTORY: usize = FPS as usize;
+
+/// Handle to a background thread that turns captured audio into [SpectrumMemory] frames
+/// The thread is stopped and joined when the handle is dropped
+pub struct Analysis {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Analysis {
+    /// Spawn the analysis thread, which publishes a new frame with the given number of bands to `output` every tick
+    pub fn spawn<T>(
+        buf: Arc<Mutex<Vec<T>>>,
+        config: &StreamConfig,
+        bands: u32,
+        output: Arc<LatestFrame<SpectrumMemory>>,
+    ) -> Self
+    where
+        T: SampleVal,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        let (channels, sample_rate) = (config.channels, config.sample_rate.0);
+
+        let handle = spawn(move || {
+            let mut spec_mem = SpectrumMemory::new(FreqIntensity::stateless_log_sections(bands, FREQ_RANGE), DECAY);
+            let mut beats = BeatDetector::default();
+
+            while running_clone.load(Ordering::Relaxed) {
+                // Constrain FPS
+                sleep(Duration::new(0, 1_000_000_000u32 / FPS));
+
+                // Get new audio frames
+                let data_buffer = take(&mut *buf.lock().unwrap());
+
+                let loudness = fft::loudness(&data_buffer, channels);
+                let fft = fft::process(data_buffer, channels, sample_rate);
+                spec_mem.tick();
+                spec_mem.push(fft.log_sections(bands, FREQ_RANGE));
+                spec_mem.push_loudness(loudness);
+                spec_mem.push_beat(beats.detect(&fft));
+
+                output.publish(spec_mem.clone());
+            }
+        });
+
+        Self { running, handle: Some(handle) }
+    }
+}
+
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Detects beats by comparing the bass energy of each frame to its recent average
+#[derive(Default)]
+struct BeatDetector {
+    history: VecDeque<f32>,
+}
+
+impl BeatDetector {
+    fn detect(&mut self, fft: &FreqIntensity) -> bool {
+        let energy: f32 =
+            fft.0.iter().filter(|(freq, _)| BASS_RANGE.contains(freq)).map(|(_, db)| 10f32.powf(db / 10.)).sum();
+        let average = self.history.iter().sum::<f32>() / self.history.len().max(1) as f32;
+
+        if self.history.len() == BEAT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(energy);
+
+        energy > 0. && energy > average * BEAT_THRESHOLD
+    }
+}
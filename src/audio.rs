@@ -1,7 +1,7 @@
 use crate::{
     options::{ActiveDevice, Options},
     tray::TrayMessage,
-    ExitState, Result, SampleVal,
+    Error, ExitState, Result, SampleVal,
 };
 
 use std::sync::{
@@ -21,9 +21,7 @@ macro_rules! format_match(
             $(
                 cpal::SampleFormat::$x => run::<lower!($x)>($d, $c, $o, $rx),
             )*
-            sample_format => {
-                panic!("Unknown format: {}", sample_format)
-            }
+            sample_format => Err(Error::UnsupportedFormat(sample_format)),
         }
     };
 );
@@ -31,18 +29,20 @@ macro_rules! format_match(
 pub fn setup(opt: Arc<RwLock<Options>>, rx: &Receiver<TrayMessage>) -> Result<ExitState> {
     let host = cpal::default_host();
 
-    let device = if let ActiveDevice::Named(ref n) = opt.read().unwrap().device {
+    // Cloned so the lock isn't held while falling back to the default device
+    let selected = opt.read().unwrap().device.clone();
+    let device = if let ActiveDevice::Named(ref n) = selected {
         if let Some(d) = host.output_devices()?.find(|x| x.name().map(|y| &y == n).unwrap_or(false)) {
             d
         } else {
             opt.write().unwrap().device = ActiveDevice::Default;
-            host.default_output_device().unwrap()
+            host.default_output_device().ok_or(Error::NoAudioDevice)?
         }
     } else {
-        host.default_output_device().unwrap()
+        host.default_output_device().ok_or(Error::NoAudioDevice)?
     };
 
-    let config = device.default_output_config().expect("Failed to get default input config");
+    let config = device.default_output_config()?;
     let form = config.sample_format();
     
     format_match!(form, device, config.into(), opt, rx, I8, I16, I32, F32)
@@ -1,7 +1,7 @@
 use strum::IntoEnumIterator;
 
 use crate::options::Options;
-use crate::themes::ThemeChoice;
+use crate::themes::{ThemeChoice, ThemeName};
 use crate::tray::TrayMessage;
 
 use std::sync::mpsc::SyncSender;
@@ -11,10 +11,13 @@ use std::time::Duration;
 
 const DELAY: Duration = Duration::from_secs(5);
 
-fn next_theme(current: &ThemeChoice) -> ThemeChoice {
+/// Only built-in themes are cycled through, starting from the first if a user theme is selected
+fn next_theme(current: &ThemeName) -> ThemeName {
     let mut choices = ThemeChoice::iter().cycle();
-    choices.find(|x| x == current);
-    choices.next().unwrap()
+    if let ThemeName::BuiltIn(current) = current {
+        choices.find(|x| x == current);
+    }
+    choices.next().unwrap().into()
 }
 
 pub fn cycle(tx: SyncSender<TrayMessage>, opt: Arc<RwLock<Options>>) {
@@ -1,5 +1,6 @@
 use crate::FreqIntensity;
 use crate::SampleVal;
+use crate::MIN_DB;
 
 use easyfft::num_complex::Complex;
 use easyfft::prelude::*;
@@ -20,7 +21,26 @@ where
         freq_intensity = Some(if let Some(v) = freq_intensity { v.combine_mean(fft) } else { fft });
     }
 
-    freq_intensity.expect("Number of channels is 0")
+    // No channels means there's nothing to analyse
+    freq_intensity.unwrap_or_default()
+}
+
+/// Calculates the loudness of each channel in DB, from the RMS of its samples
+pub fn loudness<T>(samples: &[T], channels: u16) -> Vec<f32>
+where
+    T: SampleVal,
+{
+    (0..channels.into())
+        .map(|channel| {
+            let channel: Vec<f32> = samples.iter().skip(channel).step_by(channels.into()).map(T::norm_to_f32).collect();
+            if channel.is_empty() {
+                return MIN_DB;
+            }
+
+            let rms = (channel.iter().map(|x| x * x).sum::<f32>() / channel.len() as f32).sqrt();
+            (20. * rms.log10()).max(MIN_DB)
+        })
+        .collect()
 }
 
 #[inline]
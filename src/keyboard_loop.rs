@@ -1,19 +1,30 @@
 use crate::*;
 use options::ActiveDevice;
+use themes::FrameContext;
 use tray::TrayMessage;
 
-use std::mem::take;
-use std::sync::{mpsc::Receiver, Arc, Mutex, RwLock};
+use analysis::Analysis;
+
+use std::mem::ManuallyDrop;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use cpal::{StreamConfig, StreamError};
+use strum::IntoEnumIterator;
 
 use wooting_rgb::*;
 use wooting_rgb_sys::*;
 
-const FPS: u32 = 60;
-const DECAY: f32 = 30. / FPS as f32;
+/// Frames per second, shared by the LED loop and the analysis thread feeding it
+pub const FPS: u32 = 60;
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long to wait before trying again after an error
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How often the number of USB writes avoided is logged
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
 
 pub fn draw<T>(
     buf: Arc<Mutex<Vec<T>>>,
@@ -25,26 +36,41 @@ pub fn draw<T>(
 where
     T: SampleVal,
 {
-    let theme = opt.read().unwrap().theme.get_theme();
-    let mut keyboard = Keyboard::from_meta_and_theme(get_info(), theme);
-
-    // LOOP
-    let keyboard_cols = get_info().max_columns.into();
-    let mut spec_mem = SpectrumMemory::new(FreqIntensity::stateless_log_sections(keyboard_cols, FREQ_RANGE), DECAY);
+    // Compared against the SDK's raw count, which still includes any device that couldn't be selected
+    let device_count = get_device_count();
+    let mut keyboards = connect_keyboards(&mut opt.write().unwrap());
+    tray::redraw();
 
-    loop {
-        keyboard.display(&spec_mem, opt.read().unwrap().caps_active);
+    // Analysis runs on its own thread, so a slow USB write never holds up the FFT (and vice versa)
+    // One spectrum is spanned over every enabled keyboard
+    let bands = keyboards.iter().map(|k| u32::from(k.cols())).sum();
+    let frames = Arc::new(LatestFrame::new());
+    let _analysis = Analysis::spawn(buf, config, bands, frames.clone());
 
-        // Constrain FPS
-        sleep(Duration::new(0, 1_000_000_000u32 / FPS));
+    let clock = SystemClock;
+    let start = Instant::now();
+    let mut frame = 0;
+    let mut last_stats = Instant::now();
 
-        // Get new audio frames
-        {
-            let data_buffer = take(&mut *buf.lock().unwrap());
+    // LOOP
+    loop {
+        // Wait for the newest frame, but don't stall event handling if analysis falls behind
+        if let Some(spec_mem) = frames.take_timeout(Duration::new(0, 1_000_000_000u32 / FPS)) {
+            let (overlays, tint) = {
+                let opt = opt.read().unwrap();
+                (opt.overlays(), opt.night.tint(&clock))
+            };
+            let ctx = FrameContext::new(&spec_mem, start.elapsed(), frame);
+            frame += 1;
+            for keyboard in keyboards.iter_mut() {
+                keyboard.set_tint(tint);
+                keyboard.display(&ctx, &overlays);
+            }
+        }
 
-            let fft = fft::process(data_buffer, config.channels, config.sample_rate.0);
-            spec_mem.tick();
-            spec_mem.push(fft.log_sections(keyboard_cols, FREQ_RANGE));
+        if last_stats.elapsed() >= STATS_INTERVAL {
+            last_stats = Instant::now();
+            log_stats(&keyboards, log::Level::Debug);
         }
 
         // Events
@@ -54,31 +80,196 @@ where
                 opt.write().unwrap().device = ActiveDevice::Default;
                 return Ok(ExitState::Restart);
             }
-            Ok(StreamError::BackendSpecific { err }) => {
-                eprintln!("{err}");
-                return Ok(ExitState::Exit(keyboard.close()));
-            }
+            Ok(StreamError::BackendSpecific { err }) => return Err(Error::Stream(err)),
         };
 
         match tray_rx.try_recv() {
             Err(_) => (),
             Ok(TrayMessage::ThemeReload) => {
-                let theme = opt.read().unwrap().theme;
-                keyboard.set_theme(theme.get_theme());
+                let opt = opt.read().unwrap();
+                for keyboard in keyboards.iter_mut() {
+                    // Zones fade from the keyboard's theme before it's changed
+                    keyboard.set_crossfade(opt.crossfade());
+                    keyboard.set_zones(get_zones(&opt));
+                    keyboard.set_theme(opt.theme_source(&opt.keyboard_theme(keyboard.device())));
+                    apply_output_options(keyboard, &opt);
+                }
             }
             Ok(TrayMessage::Refresh) => {
                 return Ok(ExitState::Restart);
             }
             Ok(TrayMessage::Quit) => {
-                return Ok(ExitState::Exit(keyboard.close()));
+                log_stats(&keyboards, log::Level::Info);
+                return Ok(close_all(keyboards));
             }
         }
-        if !is_wooting_keyboard_connected() {
-            return Ok(ExitState::Exit(keyboard.close()));
+        // The keyboards are rebuilt from fresh device info once they're reconnected
+        if !is_wooting_keyboard_connected() || get_device_count() != device_count {
+            log::info!("Keyboard Disconnected");
+            return Ok(ExitState::Restart);
+        }
+    }
+}
+
+/// Blocks until a keyboard is connected, polling with an exponential backoff
+/// Tray messages are still handled while waiting, so the app can be quit
+pub fn wait_for_keyboard(tray_rx: &Receiver<TrayMessage>) -> ExitState {
+    log::info!("Keyboard Not Connected");
+    let mut delay = MIN_RECONNECT_DELAY;
+
+    while !is_wooting_keyboard_connected() {
+        match tray_rx.recv_timeout(delay) {
+            Ok(TrayMessage::Quit) => return ExitState::Exit(ManuallyDrop::new(RgbKeyboard)),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => sleep(delay),
+        }
+
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+
+    ExitState::Restart
+}
+
+/// Recovers from an error that stopped the keyboards from being drawn to, as described by [Error::recovery]
+/// Tray messages are still handled while waiting to retry, so the app can be quit
+pub fn recover(e: Error, opt: &Arc<RwLock<Options>>, tray_rx: &Receiver<TrayMessage>) -> ExitState {
+    match e.recovery() {
+        Recovery::Notify => log::error!("{e}"),
+        Recovery::Retry | Recovery::DefaultDevice => log::warn!("{e}"),
+    }
+
+    match e.recovery() {
+        // If the default device is already in use, there's nothing to fall back to, so just retry
+        Recovery::DefaultDevice if opt.read().unwrap().device != ActiveDevice::Default => {
+            opt.write().unwrap().device = ActiveDevice::Default;
+            tray::redraw();
+            return ExitState::Restart;
+        }
+        Recovery::Notify => tray::notify(&e),
+        Recovery::DefaultDevice | Recovery::Retry => (),
+    }
+
+    match tray_rx.recv_timeout(RETRY_DELAY) {
+        Ok(TrayMessage::Quit) => ExitState::Exit(ManuallyDrop::new(RgbKeyboard)),
+        Ok(_) | Err(RecvTimeoutError::Timeout) => ExitState::Restart,
+        Err(RecvTimeoutError::Disconnected) => {
+            sleep(RETRY_DELAY);
+            ExitState::Restart
+        }
+    }
+}
+
+/// Builds a [Keyboard] for every enabled device, laid out left to right in device order
+/// The model names of all connected devices are stored in the options for the tray to display
+fn connect_keyboards(opt: &mut Options) -> Vec<Keyboard> {
+    let devices = get_devices();
+    for e in opt.load_user_themes() {
+        log::warn!("{e}");
+        tray::notify(&e);
+    }
+    opt.set_connected_keyboards(devices.iter().map(|(_, meta)| model_name(meta)).collect());
+
+    let mut band_offset = 0;
+    devices
+        .into_iter()
+        .filter(|(device, _)| opt.keyboard(*device).enabled)
+        .map(|(device, meta)| {
+            let theme = opt.theme_source(&opt.keyboard_theme(device));
+            let mut keyboard = Keyboard::from_meta_and_theme(device, meta, theme);
+            keyboard.set_zones(get_zones(opt));
+            keyboard.set_orientation(opt.keyboard_orientation(device));
+            keyboard.set_crossfade(opt.crossfade());
+            apply_output_options(&mut keyboard, opt);
+            keyboard.set_band_offset(band_offset);
+            band_offset += usize::from(keyboard.cols());
+            keyboard
+        })
+        .collect()
+}
+
+/// Apply the options that change how a keyboard's colours are written, rather than what's drawn
+fn apply_output_options(keyboard: &mut Keyboard, opt: &Options) {
+    keyboard.set_correction(opt.correction(keyboard.device()));
+    keyboard.set_dithering(opt.dithering);
+    keyboard.set_flash_limit(opt.flash_limit.then_some(FPS));
+}
+
+/// Log how many frames and keys were skipped by each keyboard since it was connected
+fn log_stats(keyboards: &[Keyboard], level: log::Level) {
+    for keyboard in keyboards {
+        log::log!(level, "Keyboard {}: {}", keyboard.device(), keyboard.stats());
+    }
+}
+
+fn get_zones(opt: &Options) -> Vec<ZoneSetting> {
+    Zone::iter()
+        .map(|zone| {
+            let zone_opt = opt.zone(zone);
+            (zone, zone_opt.visualisation, zone_opt.theme.map(|t| opt.theme_source(&t)))
+        })
+        .collect()
+}
+
+/// Resets every keyboard, handing back the last one's [RgbKeyboard] to be dropped on exit
+fn close_all(mut keyboards: Vec<Keyboard>) -> ExitState {
+    let last = keyboards.pop();
+    keyboards.into_iter().for_each(Keyboard::reset);
+
+    ExitState::Exit(last.map(Keyboard::close).unwrap_or(ManuallyDrop::new(RgbKeyboard)))
+}
+
+/// Resets every connected device and closes the SDK, without needing the [Keyboard]s that are drawing to them
+/// Used when the process is going down without the main loop getting the chance to call [close_all]
+/// Drawing is stopped first, so a frame being written on another thread can't undo the reset
+pub fn reset_connected() {
+    let _writing = stop_writing();
+    for device in 0..get_device_count() as u8 {
+        unsafe {
+            wooting_usb_select_device(device);
+            wooting_rgb_reset_rgb();
         }
     }
+    drop(RgbKeyboard);
+}
+
+fn get_device_count() -> usize {
+    unsafe { wooting_usb_device_count() }.into()
+}
+
+/// Gets the device info of every connected keyboard, along with the index used to select it
+fn get_devices() -> Vec<(u8, WOOTING_USB_META)> {
+    (0..get_device_count() as u8)
+        .filter(|i| {
+            let selected = unsafe { wooting_usb_select_device(*i) };
+            if !selected {
+                log::warn!("Skipping keyboard {i}, as it couldn't be selected");
+            }
+            selected
+        })
+        .map(|i| (i, get_info()))
+        .collect()
 }
 
 fn get_info() -> WOOTING_USB_META {
     unsafe { *wooting_rgb_sys::wooting_rgb_device_info() }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use themes::ThemeChoice;
+
+    #[test]
+    fn flash_limit_from_options() {
+        let theme = ThemeChoice::default().into();
+        let mut keyboard = Keyboard::new(RgbKeyboard, theme, 0, KeyLayout::grid(6, 21), 21);
+        let mut opt = Options { flash_limit: true, ..Default::default() };
+
+        apply_output_options(&mut keyboard, &opt);
+        assert!(keyboard.is_flash_limited());
+
+        opt.flash_limit = false;
+        apply_output_options(&mut keyboard, &opt);
+        assert!(!keyboard.is_flash_limited())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{Error, Result};
+
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, EnumString};
+
+/// Size a log file can grow to before it's rotated, in bytes
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// Number of old log files kept alongside the current one
+const OLD_LOGS: u32 = 3;
+
+/// How much detail is written to the log
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, PartialEq, Display, EnumString, AsRefStr, EnumIter)]
+pub enum Verbosity {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<Verbosity> for LevelFilter {
+    fn from(verbosity: Verbosity) -> Self {
+        match verbosity {
+            Verbosity::Error => Self::Error,
+            Verbosity::Warn => Self::Warn,
+            Verbosity::Info => Self::Info,
+            Verbosity::Debug => Self::Debug,
+            Verbosity::Trace => Self::Trace,
+        }
+    }
+}
+
+/// Writes log messages to a file, starting a new one when it gets too large
+/// Messages are also written to stderr on debug builds, as release builds don't have a console
+struct FileLogger {
+    path: PathBuf,
+    max_size: u64,
+    file: Mutex<Option<File>>,
+}
+
+impl FileLogger {
+    fn new(path: PathBuf, max_size: u64) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(Error::Log)?;
+        }
+        let file = open(&path)?;
+
+        Ok(Self { path, max_size, file: Mutex::new(Some(file)) })
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        // Messages have already been filtered by the max level
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        #[cfg(debug_assertions)]
+        eprint!("{line}");
+
+        // Logging must never panic, as it's used from the panic hook
+        let Ok(mut file) = self.file.lock() else { return };
+
+        if file.as_ref().and_then(|f| f.metadata().ok()).is_some_and(|m| m.len() >= self.max_size) {
+            // Closed first, as open files can't be renamed on Windows
+            *file = None;
+            rotate(&self.path);
+            *file = open(&self.path).ok();
+        }
+
+        if let Some(file) = file.as_mut() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(file) = file.as_mut() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// Start logging to the file at `path`, showing messages at or above the given verbosity
+pub fn init(path: PathBuf, verbosity: Verbosity) -> Result<()> {
+    let logger = FileLogger::new(path, MAX_LOG_SIZE)?;
+    // Only fails if a logger has already been set
+    let _ = log::set_boxed_logger(Box::new(logger));
+    set_verbosity(verbosity);
+    Ok(())
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    log::set_max_level(verbosity.into())
+}
+
+/// Open the log file in the system's text viewer
+pub fn open_log(path: &Path) -> Result<()> {
+    #[cfg(windows)]
+    let mut command = Command::new("notepad");
+    #[cfg(not(windows))]
+    let mut command = Command::new("xdg-open");
+
+    command.arg(path).spawn().map_err(Error::Log)?;
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new().create(true).append(true).open(path).map_err(Error::Log)
+}
+
+/// The path of an old log file, where 1 is the most recent
+fn old_log(path: &Path, age: u32) -> PathBuf {
+    path.with_extension(format!("{age}.log"))
+}
+
+/// Move each old log file back one place, dropping the oldest, so the current one can be started afresh
+fn rotate(path: &Path) {
+    for age in (1..OLD_LOGS).rev() {
+        let _ = rename(old_log(path, age), old_log(path, age + 1));
+    }
+    let _ = rename(path, old_log(path, 1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    use log::Level;
+
+    #[test]
+    fn rotates() {
+        let dir = TempDir::new("log");
+        let path = dir.join("logs").join("test.log");
+
+        let logger = FileLogger::new(path.clone(), 100).unwrap();
+        for i in 0..20 {
+            logger.log(&Record::builder().args(format_args!("message {i}")).level(Level::Info).build());
+        }
+        logger.flush();
+
+        assert!(path.exists());
+        for age in 1..=OLD_LOGS {
+            assert!(old_log(&path, age).exists(), "{age}");
+        }
+        assert!(!old_log(&path, OLD_LOGS + 1).exists());
+
+        let latest = std::fs::read_to_string(&path).unwrap();
+        assert!(latest.contains("INFO  ") && latest.contains("message 19"))
+    }
+}
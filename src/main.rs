@@ -1,11 +1,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod analysis;
 pub mod audio;
 mod fft;
 pub mod keyboard_loop;
+mod logging;
 pub mod options;
+mod shutdown;
 pub mod tray;
 pub mod types;
+mod watch;
 
 #[cfg(feature = "window-display")]
 pub mod draw;
@@ -21,6 +25,8 @@ pub const MIN_DB: f32 = -36.;
 pub const FREQ_RANGE: std::ops::RangeInclusive<f32> = 20.0..=16_000.0;
 
 pub const OPTIONS_FILE: &str = "options.json";
+pub const LOG_FILE: &str = "wooting-spectro.log";
+pub const THEMES_DIR: &str = "themes";
 
 // Private imports for main
 use options::Options;
@@ -36,34 +42,56 @@ fn main() -> Result<()> {
     #[cfg(not(any(windows, target_os = "linux")))]
     compile_error!("This application only targets Windows or Linux systems");
 
-    let instance = Box::new(single_instance::SingleInstance::new(env!("CARGO_BIN_NAME")).unwrap());
+    let instance = Box::new(single_instance::SingleInstance::new(env!("CARGO_BIN_NAME"))?);
     if !instance.is_single() {
         eprintln!("App Already Running");
         std::process::exit(0)
     }
 
-    if !wooting_rgb::is_wooting_keyboard_connected() {
-        eprintln!("Keyboard Not Connected");
-        std::process::exit(0)
+    // Logging is started first, so problems with the options file can be logged
+    let opt = Options::read_from_file();
+    let verbosity = opt.as_ref().map(|opt| opt.verbosity).unwrap_or_default();
+    if let Err(e) = logging::init(Options::get_log_path(), verbosity) {
+        eprintln!("{e}");
+        tray::notify(&e);
     }
+    log::info!("Starting {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    let opt = Options::read_from_file();
+    let opt = opt.unwrap_or_else(|e| {
+        log::error!("{e}");
+        tray::notify(&e);
+        Options::default()
+    });
     let opt = std::sync::Arc::new(std::sync::RwLock::new(opt));
     let opt_clone = opt.clone();
 
     let (tx, rx) = std::sync::mpsc::sync_channel(1);
+    shutdown::install(opt.clone(), tx.clone());
+    watch::spawn_watcher(opt.clone(), tx.clone());
 
     #[cfg(feature = "demo")]
     demo::cycle(tx.clone(), opt.clone());
 
-    std::thread::spawn(move || crate::tray::spawn_tray(opt_clone, tx));
+    std::thread::spawn(move || {
+        if let Err(e) = crate::tray::spawn_tray(opt_clone, tx) {
+            log::error!("{e}")
+        }
+    });
 
     loop {
-        let status = audio::setup(opt.clone(), &rx)?;
+        // Wait around with the tray alive if there's no keyboard, rather than exiting
+        let status = if wooting_rgb::is_wooting_keyboard_connected() {
+            audio::setup(opt.clone(), &rx).unwrap_or_else(|e| keyboard_loop::recover(e, &opt, &rx))
+        } else {
+            keyboard_loop::wait_for_keyboard(&rx)
+        };
 
         if let ExitState::Exit(k) = status {
-            opt.read().unwrap().write_to_file();
+            if let Err(e) = opt.read().unwrap().write_to_file() {
+                log::error!("{e}")
+            }
             ManuallyDrop::into_inner(k); // Drop
+            log::info!("Exiting");
             return Ok(());
         }
     }
@@ -1,12 +1,22 @@
-use crate::themes::ThemeChoice;
-use crate::Result;
-use crate::OPTIONS_FILE;
+use crate::logging::Verbosity;
+use crate::themes::{ThemeChoice, ThemeName, ThemeSource};
+use crate::{
+    load_user_themes, BlendMode, Calibration, Correction, Indicator, NightMode, Orientation, Overlay, Rgb, UserTheme,
+    Visualisation, Zone,
+};
+use crate::{Error, Result};
+use crate::{LOG_FILE, OPTIONS_FILE, THEMES_DIR};
 
+use std::collections::BTreeMap;
 use std::fs::{create_dir, File, OpenOptions};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json;
+use strum::IntoEnumIterator;
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ActiveDevice {
@@ -25,22 +35,306 @@ impl ActiveDevice {
     }
 }
 
+/// Per-keyboard settings, stored by [Options::keyboard_id]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyboardOptions {
+    pub enabled: bool,
+    /// Overrides [Options::theme] for this keyboard when set
+    pub theme: Option<ThemeName>,
+    /// Overrides [Options::orientation] for this keyboard when set
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+    #[serde(default)]
+    pub calibration: Calibration,
+}
+
+impl std::default::Default for KeyboardOptions {
+    fn default() -> Self {
+        Self { enabled: true, theme: None, orientation: None, calibration: Default::default() }
+    }
+}
+
+/// Settings for a [Zone] of every keyboard
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ZoneOptions {
+    pub visualisation: Visualisation,
+    /// Overrides the keyboard's theme when set
+    /// Zones showing the spectrum always use the keyboard's theme, as they're part of one continuous spectrum
+    pub theme: Option<ThemeName>,
+}
+
+/// Settings for a lock key [Indicator]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IndicatorOptions {
+    /// Ignored for Caps Lock, which is toggled by [Options::caps_active]
+    #[serde(default)]
+    pub enabled: bool,
+    pub colour: Rgb,
+    #[serde(default)]
+    pub blend: BlendMode,
+}
+
+impl std::default::Default for IndicatorOptions {
+    fn default() -> Self {
+        Self { enabled: false, colour: Rgb(255, 255, 255), blend: BlendMode::default() }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Options {
     pub device: ActiveDevice,
-    pub theme: ThemeChoice,
+    pub theme: ThemeName,
+    /// Whether the Caps Lock indicator is shown
     pub caps_active: bool,
+    /// Caps the brightness of every keyboard, from 0 to 1
+    #[serde(default = "full_brightness")]
+    pub brightness: f32,
+    /// Whether colours are dithered over time, which smooths fades but makes dim keys flicker slightly
+    #[serde(default)]
+    pub dithering: bool,
+    /// Whether flashing is limited to three times a second, for photosensitive users
+    #[serde(default)]
+    pub flash_limit: bool,
+    #[serde(default)]
+    pub night: NightMode,
+    #[serde(default)]
+    pub verbosity: Verbosity,
+    /// How long switching themes takes, in milliseconds
+    #[serde(default = "default_crossfade")]
+    pub crossfade: u64,
+    #[serde(default)]
+    pub indicators: BTreeMap<Indicator, IndicatorOptions>,
+    /// Keys that always show a fixed colour, such as WASD
+    #[serde(default)]
+    pub pinned: Vec<Overlay>,
+    #[serde(default)]
+    pub orientation: Orientation,
+    #[serde(default)]
+    pub zones: BTreeMap<Zone, ZoneOptions>,
+    #[serde(default, deserialize_with = "read_keyboards")]
+    pub keyboards: BTreeMap<String, KeyboardOptions>,
+    /// Model names of the currently connected keyboards, indexed by device
+    #[serde(skip)]
+    pub connected_keyboards: Vec<String>,
+    /// Themes loaded from the themes directory, by name
+    #[serde(skip)]
+    pub user_themes: BTreeMap<String, UserTheme>,
+}
+
+/// Keyboard settings were saved as a list in device order by earlier versions
+/// Those are keyed by their position until the keyboards are connected, and [Options::set_connected_keyboards] can
+/// match them up
+fn read_keyboards<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, KeyboardOptions>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        ById(BTreeMap<String, KeyboardOptions>),
+        InOrder(Vec<KeyboardOptions>),
+    }
+
+    Ok(match Saved::deserialize(deserializer)? {
+        Saved::ById(keyboards) => keyboards,
+        Saved::InOrder(keyboards) => {
+            keyboards.into_iter().enumerate().map(|(device, kbd_opt)| (positional_id(device), kbd_opt)).collect()
+        }
+    })
+}
+
+/// The id of a keyboard that's only known by its position in the SDK's device list
+fn positional_id(device: usize) -> String {
+    format!("#{device}")
+}
+
+fn full_brightness() -> f32 {
+    1.
+}
+
+fn default_crossfade() -> u64 {
+    500
 }
 
 impl std::default::Default for Options {
     fn default() -> Self {
-        Self { caps_active: true, device: Default::default(), theme: Default::default() }
+        Self {
+            caps_active: true,
+            brightness: full_brightness(),
+            dithering: false,
+            flash_limit: false,
+            night: Default::default(),
+            verbosity: Default::default(),
+            crossfade: default_crossfade(),
+            indicators: Default::default(),
+            pinned: Default::default(),
+            device: Default::default(),
+            theme: Default::default(),
+            orientation: Default::default(),
+            zones: Default::default(),
+            keyboards: Default::default(),
+            connected_keyboards: Default::default(),
+            user_themes: Default::default(),
+        }
     }
 }
 
 impl Options {
+    /// The name a connected keyboard's settings are stored under, which is its model name
+    /// The SDK doesn't report serial numbers, so further keyboards of the same model are numbered in the order the SDK
+    /// lists them, and two of the same model can swap settings if they're plugged in the other way round
+    pub fn keyboard_id(&self, device: u8) -> String {
+        let device = usize::from(device);
+        let Some(model) = self.connected_keyboards.get(device) else { return positional_id(device) };
+
+        match self.connected_keyboards[..device].iter().filter(|m| *m == model).count() {
+            0 => model.clone(),
+            earlier => format!("{model} ({})", earlier + 1),
+        }
+    }
+
+    /// Record the model names of the connected keyboards, in device order
+    /// Settings saved by position in earlier versions are moved over to the keyboard now in that position
+    pub fn set_connected_keyboards(&mut self, models: Vec<String>) {
+        self.connected_keyboards = models;
+
+        for device in 0..self.connected_keyboards.len() {
+            if let Some(kbd_opt) = self.keyboards.remove(&positional_id(device)) {
+                let id = self.keyboard_id(device as u8);
+                self.keyboards.entry(id).or_insert(kbd_opt);
+            }
+        }
+    }
+
+    /// Gets the settings for the keyboard with the given device index
+    pub fn keyboard(&self, device: u8) -> KeyboardOptions {
+        self.keyboards.get(&self.keyboard_id(device)).cloned().unwrap_or_default()
+    }
+
+    /// Gets a mutable reference to the settings for a keyboard, filling in defaults if it hasn't got any yet
+    pub fn keyboard_mut(&mut self, device: u8) -> &mut KeyboardOptions {
+        let id = self.keyboard_id(device);
+        self.keyboards.entry(id).or_default()
+    }
+
+    /// Gets the settings for a zone
+    pub fn zone(&self, zone: Zone) -> ZoneOptions {
+        self.zones.get(&zone).cloned().unwrap_or_default()
+    }
+
+    /// Gets the settings for a lock key indicator
+    pub fn indicator(&self, indicator: Indicator) -> IndicatorOptions {
+        let mut indicator_opt = self.indicators.get(&indicator).cloned().unwrap_or_default();
+        if indicator == Indicator::CapsLock {
+            indicator_opt.enabled = self.caps_active;
+        }
+        indicator_opt
+    }
+
+    /// Gets the overlays to draw on top of the spectrum this frame, which depend on the state of the lock keys
+    /// Pinned keys are drawn first, so indicators stay visible if they overlap
+    pub fn overlays(&self) -> Vec<Overlay> {
+        let indicators = Indicator::iter().filter_map(|indicator| {
+            let indicator_opt = self.indicator(indicator);
+            (indicator_opt.enabled && indicator.is_on()).then(|| Overlay {
+                keys: vec![indicator.matrix()],
+                colour: indicator_opt.colour.into(),
+                blend: indicator_opt.blend,
+            })
+        });
+
+        self.pinned.iter().cloned().chain(indicators).collect()
+    }
+
+    /// Gets the colour correction for a keyboard, combining the global brightness with its calibration
+    pub fn correction(&self, device: u8) -> Correction {
+        Correction::new(self.brightness, self.keyboard(device).calibration)
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        Duration::from_millis(self.crossfade)
+    }
+
+    /// Gets the theme to display on a keyboard, falling back on the global theme
+    pub fn keyboard_theme(&self, device: u8) -> ThemeName {
+        self.keyboard(device).theme.unwrap_or_else(|| self.theme.clone())
+    }
+
+    /// Gets how bands are laid out on a keyboard, falling back on the global orientation
+    pub fn keyboard_orientation(&self, device: u8) -> Orientation {
+        self.keyboard(device).orientation.unwrap_or(self.orientation)
+    }
+
+    pub fn theme_source(&self, name: &ThemeName) -> ThemeSource {
+        name.source(&self.user_themes)
+    }
+
+    /// Every theme that can be selected, with the built-in themes first
+    /// User themes named after a built-in theme are left out, as the name always selects the built-in one
+    pub fn theme_names(&self) -> Vec<ThemeName> {
+        let user_themes = self
+            .user_themes
+            .keys()
+            .filter(|name| ThemeChoice::from_str(name).is_err())
+            .map(|name| ThemeName::User(name.clone()));
+        ThemeChoice::iter().map(ThemeName::BuiltIn).chain(user_themes).collect()
+    }
+
+    /// Reload the user themes from disk, returning any that couldn't be loaded
+    /// A theme that fails to load keeps its previous version, so a half-finished edit doesn't change the lighting
+    pub fn load_user_themes(&mut self) -> Vec<Error> {
+        self.load_user_themes_from(&Self::get_themes_path())
+    }
+
+    fn load_user_themes_from(&mut self, dir: &Path) -> Vec<Error> {
+        let themes = match load_user_themes(dir) {
+            Ok(themes) => themes,
+            Err(e) => return vec![e],
+        };
+
+        let mut errors = Vec::new();
+        let mut previous = std::mem::take(&mut self.user_themes);
+        for (name, theme) in themes {
+            match theme {
+                Ok(theme) => {
+                    self.user_themes.insert(name, theme);
+                }
+                Err(e) => {
+                    errors.push(e);
+                    if let Some(theme) = previous.remove(&name) {
+                        self.user_themes.insert(name, theme);
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Whether switching to other options means drawing has to be restarted, rather than just reloading the themes
+    /// This is the case when the audio device or number of bands changes
+    pub fn needs_restart(&self, other: &Self) -> bool {
+        let devices = self.connected_keyboards.len() as u8;
+
+        self.device != other.device
+            || self.orientation != other.orientation
+            || Zone::iter().any(|zone| self.zone(zone).visualisation != other.zone(zone).visualisation)
+            || (0..devices).any(|device| {
+                self.keyboard(device).enabled != other.keyboard(device).enabled
+                    || self.keyboard_orientation(device) != other.keyboard_orientation(device)
+            })
+    }
+
+    /// The log is kept beside the options, so it's easy to find
+    pub fn get_log_path() -> PathBuf {
+        Self::get_options_path().with_file_name(LOG_FILE)
+    }
+
+    /// User themes are kept in a directory beside the options
+    pub fn get_themes_path() -> PathBuf {
+        Self::get_options_path().with_file_name(THEMES_DIR)
+    }
+
     #[allow(unused_mut)]
-    fn get_options_path() -> PathBuf {
+    pub fn get_options_path() -> PathBuf {
         let mut path = PathBuf::from("./").join(OPTIONS_FILE);
 
         #[cfg(feature = "cli")]
@@ -51,9 +345,12 @@ impl Options {
         path
     }
 
-    pub fn read_from_file() -> Self {
-        println!("{:?}",Self::get_options_path());
-        Self::read_result(Self::get_options_path()).unwrap_or_default()
+    /// Read the saved options, or the defaults if none have been saved yet
+    pub fn read_from_file() -> Result<Self> {
+        match Self::read_result(Self::get_options_path()) {
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
     }
 
     fn read_result(path: impl AsRef<Path>) -> Result<Self> {
@@ -63,14 +360,128 @@ impl Options {
         Ok(serde_json::from_reader(reader)?)
     }
 
-    pub fn write_to_file(&self) {
-        let path = Self::get_options_path();
-        let dir = path.parent().unwrap();
-        if !dir.exists() {
-            create_dir(dir).unwrap()
+    pub fn write_to_file(&self) -> Result<()> {
+        self.write_to(&Self::get_options_path())
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            if !dir.exists() {
+                create_dir(dir)?
+            }
         }
 
-        let f = OpenOptions::new().create(true).write(true).truncate(true).open(path).unwrap();
-        serde_json::to_writer_pretty(f, self).unwrap()
+        let f = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        Ok(serde_json::to_writer_pretty(f, self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected(models: &[&str]) -> Options {
+        let mut opt = Options::default();
+        opt.set_connected_keyboards(models.iter().map(|m| m.to_string()).collect());
+        opt
+    }
+
+    #[test]
+    fn keyboard_ids() {
+        let opt = connected(&["Wooting Two HE", "Wooting 60HE", "Wooting Two HE"]);
+        assert_eq!(opt.keyboard_id(0), "Wooting Two HE");
+        assert_eq!(opt.keyboard_id(1), "Wooting 60HE");
+        assert_eq!(opt.keyboard_id(2), "Wooting Two HE (2)");
+        assert_eq!(opt.keyboard_id(3), "#3")
+    }
+
+    #[test]
+    fn settings_follow_model() {
+        let mut opt = connected(&["Wooting Two HE", "Wooting 60HE"]);
+        opt.keyboard_mut(1).enabled = false;
+
+        // Plugged in the other way round
+        opt.set_connected_keyboards(vec![String::from("Wooting 60HE"), String::from("Wooting Two HE")]);
+        assert!(!opt.keyboard(0).enabled);
+        assert!(opt.keyboard(1).enabled)
+    }
+
+    #[test]
+    fn positional_settings_adopted() {
+        let json = r#"{ "device": "Default", "theme": "Classic", "caps_active": true,
+            "keyboards": [{ "enabled": true, "theme": null }, { "enabled": false, "theme": null }] }"#;
+        let mut opt: Options = serde_json::from_str(json).unwrap();
+        opt.set_connected_keyboards(vec![String::from("Wooting One"), String::from("Wooting Two")]);
+
+        assert!(!opt.keyboard(1).enabled);
+        assert_eq!(opt.keyboards.keys().collect::<Vec<_>>(), ["Wooting One", "Wooting Two"])
+    }
+
+    #[test]
+    fn built_in_names_shadow_user_themes() {
+        let mut opt = Options::default();
+        let theme = UserTheme::Gradient(serde_json::from_str(r#"{ "stops": [] }"#).unwrap());
+        opt.user_themes.insert(String::from("Fire"), theme.clone());
+        opt.user_themes.insert(String::from("Sunset"), theme);
+
+        let names = opt.theme_names();
+        assert_eq!(names.iter().filter(|name| name.to_string() == "Fire").count(), 1);
+        assert!(names.contains(&ThemeName::User(String::from("Sunset"))))
+    }
+
+    #[test]
+    fn reload_user_themes() {
+        let dir = crate::TempDir::new("options-themes");
+        let path = dir.join("Sunset.json");
+        let mut opt = Options::default();
+        let stops = |opt: &Options| match &opt.user_themes["Sunset"] {
+            UserTheme::Gradient(theme) => theme.stops.len(),
+            UserTheme::Script(_) => panic!("Not a gradient"),
+        };
+
+        std::fs::write(&path, r#"{ "stops": [{ "position": 0, "colour": [0, 0, 0] }] }"#).unwrap();
+        assert!(opt.load_user_themes_from(&dir).is_empty());
+        assert_eq!(stops(&opt), 1);
+
+        // A broken rewrite keeps the previous version
+        std::fs::write(&path, r#"{ "stops": ["#).unwrap();
+        assert_eq!(opt.load_user_themes_from(&dir).len(), 1);
+        assert_eq!(stops(&opt), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(opt.load_user_themes_from(&dir).is_empty());
+        assert!(opt.user_themes.is_empty())
+    }
+
+    #[test]
+    fn needs_restart() {
+        let opt = connected(&["Wooting One"]);
+        let changed = |change: fn(&mut Options)| {
+            let mut other = opt.clone();
+            change(&mut other);
+            opt.needs_restart(&other)
+        };
+
+        assert!(changed(|o| o.device = ActiveDevice::Named(String::from("Speakers"))));
+        assert!(changed(|o| o.orientation = Orientation::Hanging));
+        assert!(changed(|o| o.keyboard_mut(0).orientation = Some(Orientation::Snake)));
+        assert!(changed(|o| o.zones.entry(Zone::Numpad).or_default().visualisation = Visualisation::Pulse));
+        assert!(changed(|o| o.keyboard_mut(0).enabled = false));
+
+        assert!(!changed(|_| ()));
+        assert!(!changed(|o| o.theme = ThemeChoice::Fire.into()));
+        assert!(!changed(|o| o.keyboard_mut(0).theme = Some(ThemeChoice::Grape.into())));
+        assert!(!changed(|o| o.brightness = 0.5));
+        assert!(!changed(|o| o.dithering = true))
+    }
+
+    #[test]
+    fn keyboard_orientation() {
+        let mut opt = connected(&["Wooting One", "Wooting Two"]);
+        opt.orientation = Orientation::Mirrored;
+        opt.keyboard_mut(1).orientation = Some(Orientation::Hanging);
+
+        assert_eq!(opt.keyboard_orientation(0), Orientation::Mirrored);
+        assert_eq!(opt.keyboard_orientation(1), Orientation::Hanging)
     }
 }
@@ -0,0 +1,61 @@
+use crate::keyboard_loop;
+use crate::options::Options;
+use crate::tray::TrayMessage;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, RwLock, TryLockError};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// How long the main loop is given to shut down normally after a signal, before the keyboard is restored regardless
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Set once the keyboard has been restored, so it's only done once however the process ends
+static RESTORED: AtomicBool = AtomicBool::new(false);
+
+/// Make sure the keyboard is restored and options are saved if the app panics or is asked to stop by the OS
+/// Without this, the keyboard is left frozen on the last frame drawn
+pub fn install(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
+    let panic_opt = opt.clone();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        log::error!("{info}");
+        restore(&panic_opt);
+        // Any panic leaves the app in an unknown state, and drawing would continue if it happened on another thread
+        std::process::exit(101);
+    }));
+
+    let result = ctrlc::set_handler(move || {
+        // Give the main loop the chance to quit the usual way, but don't rely on it
+        log::info!("Received termination signal");
+        let _ = tx.try_send(TrayMessage::Quit);
+        sleep(SHUTDOWN_GRACE);
+        restore(&opt);
+        std::process::exit(0);
+    });
+    if let Err(e) = result {
+        log::error!("{e}")
+    }
+}
+
+/// Reset every keyboard, then save the options
+/// Options aren't saved if another thread is in the middle of changing them, as waiting could deadlock
+fn restore(opt: &RwLock<Options>) {
+    if RESTORED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    // The keyboard comes first, in case saving fails
+    keyboard_loop::reset_connected();
+
+    let result = match opt.try_read() {
+        Ok(opt) => opt.write_to_file(),
+        Err(TryLockError::Poisoned(e)) => e.into_inner().write_to_file(),
+        Err(TryLockError::WouldBlock) => Ok(()),
+    };
+    if let Err(e) = result {
+        log::warn!("{e}")
+    }
+}
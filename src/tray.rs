@@ -1,10 +1,15 @@
 use crate::audio;
+use crate::logging::{self, Verbosity};
 use crate::options::*;
-use crate::themes::ThemeChoice;
+use crate::themes::ThemeName;
+use crate::watch;
+use crate::{Error, Indicator, Orientation, Result, Visualisation, Zone};
 
 use std::str::FromStr;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc::SyncSender, Arc};
+use std::sync::{Mutex, RwLock};
+use std::thread;
 
 use strum::IntoEnumIterator;
 use tray_icon::{menu::*, TrayIconBuilder};
@@ -16,6 +21,16 @@ use winit::platform::windows::EventLoopBuilderExtWindows as _;
 const TICK: &str = "•";
 const NO_TICK: &str = "";
 
+/// Brightness levels selectable from the menu, as percentages
+const BRIGHTNESS_LEVELS: [u32; 10] = [10, 20, 30, 40, 50, 60, 70, 80, 90, 100];
+/// Gamma values selectable for each keyboard, where 1 means no correction
+const GAMMAS: [f32; 4] = [1.0, 1.8, 2.2, 2.6];
+
+/// Proxy into the tray's event loop, so the menu can be redrawn from other threads
+static PROXY: Mutex<Option<EventLoopProxy<EventLoopMessage>>> = Mutex::new(None);
+/// An error to show at the top of the menu until it's clicked
+static NOTICE: Mutex<Option<String>> = Mutex::new(None);
+
 /// Stores the potential messages that can be sent by the tray
 #[derive(Debug)]
 pub enum TrayMessage {
@@ -26,11 +41,12 @@ pub enum TrayMessage {
 
 #[derive(Debug)]
 enum EventLoopMessage {
-    Redraw(Arc<RwLock<Options>>),
+    Redraw,
 }
 
 struct Loop {
     menu: Menu,
+    opt: Arc<RwLock<Options>>,
 }
 
 impl winit::application::ApplicationHandler<EventLoopMessage> for Loop {
@@ -46,16 +62,20 @@ impl winit::application::ApplicationHandler<EventLoopMessage> for Loop {
 
     fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: EventLoopMessage) {
         match event {
-            EventLoopMessage::Redraw(opt) => draw_menu(& self.menu, &opt),
+            EventLoopMessage::Redraw => {
+                if let Err(e) = draw_menu(&self.menu, &self.opt) {
+                    log::error!("{e}")
+                }
+            }
         }
     }
 }
 
 /// Creates an application within the system tray that can send [TrayMessage]s to the main thread
-pub fn spawn_tray(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
+pub fn spawn_tray(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) -> Result<()> {
     let tray_menu = Menu::new();
 
-    draw_menu(&tray_menu, &opt);
+    draw_menu(&tray_menu, &opt)?;
 
     // Since winit doesn't use gtk on Linux, and we need gtk for
     // the tray icon to show up, we need to spawn a thread
@@ -64,16 +84,21 @@ pub fn spawn_tray(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
     std::thread::spawn(|| {
         use tray_icon::menu::Menu;
 
-        let icon = get_icon();
-
-        gtk::init().unwrap();
-        let _tray_icon = TrayIconBuilder::new().with_menu(Box::new(Menu::new())).with_icon(icon).build().unwrap();
+        if gtk::init().is_err() {
+            log::error!("Failed to initialise GTK");
+            return;
+        }
+        let tray_icon = get_icon()
+            .and_then(|icon| Ok(TrayIconBuilder::new().with_menu(Box::new(Menu::new())).with_icon(icon).build()?));
 
-        gtk::main();
+        match tray_icon {
+            Ok(_tray_icon) => gtk::main(),
+            Err(e) => log::error!("{e}"),
+        }
     });
 
     #[cfg(not(target_os = "linux"))]
-    let icon = get_icon();
+    let icon = get_icon()?;
     #[cfg(not(target_os = "linux"))]
     let _tray_icon = Some(
         TrayIconBuilder::new()
@@ -82,27 +107,61 @@ pub fn spawn_tray(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
             .with_tooltip("Wooting Spectro - Running")
             .with_menu_on_left_click(true)
             .with_icon(icon)
-            .build()
-            .unwrap(),
+            .build()?,
     );
 
     // #[cfg(windows)]
-    let event_loop = EventLoop::<EventLoopMessage>::with_user_event().with_any_thread(true).build().unwrap();
+    let event_loop = EventLoop::<EventLoopMessage>::with_user_event().with_any_thread(true).build()?;
 
     let proxy: EventLoopProxy<EventLoopMessage> = event_loop.create_proxy();
+    *PROXY.lock().unwrap_or_else(|e| e.into_inner()) = Some(event_loop.create_proxy());
+
+    let handler_opt = opt.clone();
+    MenuEvent::set_event_handler(Some(move |e| handle_event(e, &tx, &handler_opt, &proxy)));
 
-    MenuEvent::set_event_handler(Some(move |e| handle_event(e, &tx, &opt, &proxy)));
+    Ok(event_loop.run_app(&mut Loop { menu: tray_menu, opt })?)
+}
+
+/// Redraws the tray menu to reflect changes made outside of the tray, such as keyboards being connected
+pub fn redraw() {
+    if let Some(proxy) = PROXY.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        let _ = proxy.send_event(EventLoopMessage::Redraw);
+    }
+}
+
+/// Shows an error at the top of the menu, which is dismissed by clicking on it
+/// Errors from before the tray has started are shown once it has
+pub fn notify(error: &Error) {
+    set_notice(error);
+    redraw();
+}
 
-    let _ = event_loop.run_app(&mut Loop { menu: tray_menu });
+/// Sets the notice without redrawing, for errors found while the menu is being drawn
+fn set_notice(error: &Error) {
+    *NOTICE.lock().unwrap_or_else(|e| e.into_inner()) = Some(error.to_string());
 }
 
 fn empty_menu(menu: &Menu) {
     while menu.remove_at(0).is_some() {}
 }
 
-fn draw_menu(menu: &Menu, opt: &Arc<RwLock<Options>>) {
+fn draw_menu(menu: &Menu, opt: &Arc<RwLock<Options>>) -> Result<()> {
     empty_menu(menu);
 
+    // Read before the notice is drawn, so an error here is shown straight away
+    let audio_devices = audio::get_devices().unwrap_or_else(|e| {
+        log::error!("{e}");
+        set_notice(&e);
+        Vec::new()
+    });
+
+    if let Some(notice) = NOTICE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        menu.append_items(&[
+            &MenuItem::with_id("Notice:", format!("⚠ {notice}"), true, None),
+            &PredefinedMenuItem::separator(),
+        ])?;
+    }
+
     let about = PredefinedMenuItem::about(
         None,
         Some(AboutMetadata {
@@ -115,44 +174,206 @@ fn draw_menu(menu: &Menu, opt: &Arc<RwLock<Options>>) {
         }),
     );
 
-    let devices = SubmenuBuilder::new().text("Devices").enabled(true).build().unwrap();
+    let devices = SubmenuBuilder::new().text("Devices").enabled(true).build()?;
     let selected = matches!(opt.read().unwrap().device, ActiveDevice::Default);
-    devices
-        .append(&MenuItem::with_id(
-            "Devices:Default",
-            format!("{} Default", if selected { TICK } else { NO_TICK }),
+    devices.append(&MenuItem::with_id(
+        "Devices:Default",
+        format!("{} Default", if selected { TICK } else { NO_TICK }),
+        true,
+        None,
+    ))?;
+    if audio_devices.is_empty() {
+        devices.append(&MenuItem::with_id("Devices:None", format!("{NO_TICK} No devices"), false, None))?;
+    }
+    for name in audio_devices {
+        let selected = matches!(&opt.read().unwrap().device,ActiveDevice::Named(s) if s == &name);
+        devices.append(&MenuItem::with_id(
+            format!("Devices:{name}"),
+            format!("{} {name}", if selected { TICK } else { NO_TICK }),
             true,
             None,
-        ))
-        .unwrap();
-    for name in audio::get_devices().unwrap() {
-        let selected = matches!(&opt.read().unwrap().device,ActiveDevice::Named(s) if s == &name);
-        devices
-            .append(&MenuItem::with_id(
-                format!("Devices:{name}"),
-                format!("{} {name}", if selected { TICK } else { NO_TICK }),
+        ))?;
+    }
+
+    let themes = SubmenuBuilder::new().text("Themes").enabled(true).build()?;
+    let theme_names = opt.read().unwrap().theme_names();
+    for theme in theme_names.iter() {
+        let selected = *theme == opt.read().unwrap().theme;
+        themes.append(&MenuItem::with_id(
+            format!("Themes:{theme}"),
+            format!("{} {theme}", if selected { TICK } else { NO_TICK }),
+            true,
+            None,
+        ))?;
+    }
+    themes.append_items(&[
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id("ImportImages:", "Import Images", true, None),
+    ])?;
+
+    let brightness = SubmenuBuilder::new().text("Brightness").enabled(true).build()?;
+    for level in BRIGHTNESS_LEVELS {
+        let selected = (opt.read().unwrap().brightness * 100.).round() as u32 == level;
+        brightness.append(&MenuItem::with_id(
+            format!("Brightness:{level}"),
+            format!("{} {level}%", if selected { TICK } else { NO_TICK }),
+            true,
+            None,
+        ))?;
+    }
+    brightness.append_items(&[
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id(
+            "Dithering:",
+            format!("{} Dithering", if opt.read().unwrap().dithering { TICK } else { NO_TICK }),
+            true,
+            None,
+        ),
+        &MenuItem::with_id(
+            "NightMode:",
+            format!("{} Night Mode", if opt.read().unwrap().night.enabled { TICK } else { NO_TICK }),
+            true,
+            None,
+        ),
+    ])?;
+
+    let orientations = SubmenuBuilder::new().text("Orientation").enabled(true).build()?;
+    for orientation in Orientation::iter() {
+        let selected = orientation == opt.read().unwrap().orientation;
+        orientations.append(&MenuItem::with_id(
+            format!("Orientation:{orientation}"),
+            format!("{} {orientation}", if selected { TICK } else { NO_TICK }),
+            true,
+            None,
+        ))?;
+    }
+
+    let zones = SubmenuBuilder::new().text("Zones").enabled(true).build()?;
+    for zone in Zone::iter() {
+        let zone_opt = opt.read().unwrap().zone(zone);
+        let zone_menu = SubmenuBuilder::new().text(zone).enabled(true).build()?;
+
+        for visualisation in Visualisation::iter() {
+            let selected = visualisation == zone_opt.visualisation;
+            zone_menu.append(&MenuItem::with_id(
+                format!("Zones:{zone}:Visualisation:{visualisation}"),
+                format!("{} {visualisation}", if selected { TICK } else { NO_TICK }),
                 true,
                 None,
-            ))
-            .unwrap();
+            ))?;
+        }
+
+        zone_menu.append_items(&[
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(
+                format!("Zones:{zone}:Theme:Keyboard"),
+                format!("{} Keyboard Theme", if zone_opt.theme.is_none() { TICK } else { NO_TICK }),
+                true,
+                None,
+            ),
+        ])?;
+        for theme in theme_names.iter() {
+            let selected = zone_opt.theme.as_ref() == Some(theme);
+            zone_menu.append(&MenuItem::with_id(
+                format!("Zones:{zone}:Theme:{theme}"),
+                format!("{} {theme}", if selected { TICK } else { NO_TICK }),
+                true,
+                None,
+            ))?;
+        }
+
+        zones.append(&zone_menu)?;
     }
 
-    let themes = SubmenuBuilder::new().text("Themes").enabled(true).build().unwrap();
-    for theme in ThemeChoice::iter() {
-        let selected = theme == opt.read().unwrap().theme;
-        themes
-            .append(&MenuItem::with_id(
-                format!("Themes:{theme}"),
+    let keyboards = SubmenuBuilder::new().text("Keyboards").enabled(true).build()?;
+    for (i, name) in opt.read().unwrap().connected_keyboards.iter().enumerate() {
+        let kbd_opt = opt.read().unwrap().keyboard(i as u8);
+        let keyboard = SubmenuBuilder::new().text(format!("{}: {name}", i + 1)).enabled(true).build()?;
+
+        keyboard.append_items(&[
+            &MenuItem::with_id(
+                format!("Keyboards:{i}:Enabled"),
+                format!("{} Enabled", if kbd_opt.enabled { TICK } else { NO_TICK }),
+                true,
+                None,
+            ),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(
+                format!("Keyboards:{i}:Theme:Global"),
+                format!("{} Global Theme", if kbd_opt.theme.is_none() { TICK } else { NO_TICK }),
+                true,
+                None,
+            ),
+        ])?;
+        for theme in theme_names.iter() {
+            let selected = kbd_opt.theme.as_ref() == Some(theme);
+            keyboard.append(&MenuItem::with_id(
+                format!("Keyboards:{i}:Theme:{theme}"),
                 format!("{} {theme}", if selected { TICK } else { NO_TICK }),
                 true,
                 None,
-            ))
-            .unwrap();
+            ))?;
+        }
+
+        keyboard.append_items(&[
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(
+                format!("Keyboards:{i}:Orientation:Global"),
+                format!("{} Global Orientation", if kbd_opt.orientation.is_none() { TICK } else { NO_TICK }),
+                true,
+                None,
+            ),
+        ])?;
+        for orientation in Orientation::iter() {
+            let selected = kbd_opt.orientation == Some(orientation);
+            keyboard.append(&MenuItem::with_id(
+                format!("Keyboards:{i}:Orientation:{orientation}"),
+                format!("{} {orientation}", if selected { TICK } else { NO_TICK }),
+                true,
+                None,
+            ))?;
+        }
+
+        keyboard.append(&PredefinedMenuItem::separator())?;
+        for gamma in GAMMAS {
+            let selected = kbd_opt.calibration.gamma == gamma;
+            keyboard.append(&MenuItem::with_id(
+                format!("Keyboards:{i}:Gamma:{gamma}"),
+                format!("{} Gamma {gamma:.1}", if selected { TICK } else { NO_TICK }),
+                true,
+                None,
+            ))?;
+        }
+
+        keyboards.append(&keyboard)?;
     }
 
-    let caps_display_name =
-        format!("{} Caps Lock Indicator", if opt.read().unwrap().caps_active { TICK } else { NO_TICK });
-    let toggle_caps = MenuItem::with_id("Caps:", caps_display_name, true, None);
+    let indicators = SubmenuBuilder::new().text("Indicators").enabled(true).build()?;
+    for indicator in Indicator::iter() {
+        let selected = opt.read().unwrap().indicator(indicator).enabled;
+        indicators.append(&MenuItem::with_id(
+            format!("Indicators:{indicator}"),
+            format!("{} {indicator}", if selected { TICK } else { NO_TICK }),
+            true,
+            None,
+        ))?;
+    }
+
+    let flash_limit_name = format!("{} Limit Flashing", if opt.read().unwrap().flash_limit { TICK } else { NO_TICK });
+    let toggle_flash_limit = MenuItem::with_id("FlashLimit:", flash_limit_name, true, None);
+
+    let logging = SubmenuBuilder::new().text("Logging").enabled(true).build()?;
+    for verbosity in Verbosity::iter() {
+        let selected = opt.read().unwrap().verbosity == verbosity;
+        logging.append(&MenuItem::with_id(
+            format!("Logging:{verbosity}"),
+            format!("{} {verbosity}", if selected { TICK } else { NO_TICK }),
+            true,
+            None,
+        ))?;
+    }
+    logging.append(&PredefinedMenuItem::separator())?;
+    logging.append(&MenuItem::with_id("OpenLog:", "Open Log", true, None))?;
 
     let refresh = MenuItem::with_id("Refresh:", "Refresh", true, None);
     let quit = MenuItem::with_id("Quit:", "Quit", true, None);
@@ -162,13 +383,20 @@ fn draw_menu(menu: &Menu, opt: &Arc<RwLock<Options>>) {
         &PredefinedMenuItem::separator(),
         &devices,
         &themes,
+        &brightness,
+        &orientations,
+        &zones,
+        &keyboards,
         &PredefinedMenuItem::separator(),
-        &toggle_caps,
+        &indicators,
+        &toggle_flash_limit,
         &PredefinedMenuItem::separator(),
+        &logging,
         &refresh,
         &quit,
-    ])
-    .unwrap();
+    ])?;
+
+    Ok(())
 }
 
 fn handle_event(
@@ -177,38 +405,174 @@ fn handle_event(
     opt: &Arc<RwLock<Options>>,
     proxy: &EventLoopProxy<EventLoopMessage>,
 ) {
-    let id = &event.id().0;
+    let before = serde_json::to_value(&*opt.read().unwrap()).ok();
+    if let Err(e) = apply_event(&event.id().0, tx, opt) {
+        log::error!("{e}")
+    }
+
+    // Saved straight away, as the file is reloaded whenever it's edited by hand
+    let opt = opt.read().unwrap();
+    if serde_json::to_value(&*opt).ok() != before {
+        if let Err(e) = watch::save_options(&opt) {
+            log::error!("{e}");
+            notify(&e);
+        }
+    }
+
+    let _ = proxy.send_event(EventLoopMessage::Redraw);
+}
+
+/// Update the options for a menu item that was clicked, telling the main loop what needs reloading
+fn apply_event(id: &str, tx: &SyncSender<TrayMessage>, opt: &Arc<RwLock<Options>>) -> Result<()> {
     let mut tree = id.split(':');
-    if let Some(base) = tree.next() {
-        if let Err(e) = match base {
-            "Devices" => {
-                opt.write().unwrap().device = ActiveDevice::from_string(tree.next().unwrap().to_string());
-                tx.send(TrayMessage::Refresh)
+    match tree.next() {
+        Some("Devices") => {
+            opt.write().unwrap().device = ActiveDevice::from_string(parse(tree.next(), id)?);
+            tx.send(TrayMessage::Refresh)?
+        }
+        Some("Themes") => {
+            opt.write().unwrap().theme = parse(tree.next(), id)?;
+            tx.send(TrayMessage::ThemeReload)?
+        }
+        Some("Brightness") => {
+            let level: f32 = parse(tree.next(), id)?;
+            opt.write().unwrap().brightness = level / 100.;
+            tx.send(TrayMessage::ThemeReload)?
+        }
+        Some("Dithering") => {
+            opt.write().unwrap().dithering ^= true;
+            tx.send(TrayMessage::ThemeReload)?
+        }
+        Some("NightMode") => {
+            // The schedule is checked every frame, so nothing needs reloading
+            opt.write().unwrap().night.enabled ^= true;
+        }
+        Some("FlashLimit") => {
+            opt.write().unwrap().flash_limit ^= true;
+            tx.send(TrayMessage::ThemeReload)?
+        }
+        Some("Orientation") => {
+            opt.write().unwrap().orientation = parse(tree.next(), id)?;
+            // The number of bands depends on the orientation
+            tx.send(TrayMessage::Refresh)?
+        }
+        Some("Zones") => {
+            let zone: Zone = parse(tree.next(), id)?;
+            match tree.next() {
+                Some("Visualisation") => {
+                    let visualisation: Visualisation = parse(tree.next(), id)?;
+                    opt.write().unwrap().zones.entry(zone).or_default().visualisation = visualisation;
+                    // Changing which zones show the spectrum changes the number of bands
+                    tx.send(TrayMessage::Refresh)?
+                }
+                Some("Theme") => {
+                    let theme: Option<ThemeName> = match tree.next() {
+                        Some("Keyboard") => None,
+                        theme => Some(parse(theme, id)?),
+                    };
+                    opt.write().unwrap().zones.entry(zone).or_default().theme = theme;
+                    tx.send(TrayMessage::ThemeReload)?
+                }
+                _ => return Err(Error::MenuId(id.to_string())),
             }
-            "Themes" => {
-                opt.write().unwrap().theme = ThemeChoice::from_str(tree.next().unwrap()).unwrap();
-                tx.send(TrayMessage::ThemeReload)
+        }
+        Some("Keyboards") => {
+            let device = parse(tree.next(), id)?;
+            match tree.next() {
+                Some("Enabled") => {
+                    opt.write().unwrap().keyboard_mut(device).enabled ^= true;
+                    // Changing which keyboards are used changes the number of bands
+                    tx.send(TrayMessage::Refresh)?
+                }
+                Some("Theme") => {
+                    let theme: Option<ThemeName> = match tree.next() {
+                        Some("Global") => None,
+                        theme => Some(parse(theme, id)?),
+                    };
+                    opt.write().unwrap().keyboard_mut(device).theme = theme;
+                    tx.send(TrayMessage::ThemeReload)?
+                }
+                Some("Orientation") => {
+                    let orientation: Option<Orientation> = match tree.next() {
+                        Some("Global") => None,
+                        orientation => Some(parse(orientation, id)?),
+                    };
+                    opt.write().unwrap().keyboard_mut(device).orientation = orientation;
+                    // The number of bands depends on the orientation
+                    tx.send(TrayMessage::Refresh)?
+                }
+                Some("Gamma") => {
+                    let gamma = parse(tree.next(), id)?;
+                    opt.write().unwrap().keyboard_mut(device).calibration.gamma = gamma;
+                    tx.send(TrayMessage::ThemeReload)?
+                }
+                _ => return Err(Error::MenuId(id.to_string())),
             }
-            "Caps" => {
-                opt.write().unwrap().caps_active ^= true;
-                Ok(())
+        }
+        Some("Indicators") => {
+            let indicator = parse(tree.next(), id)?;
+            let mut opt = opt.write().unwrap();
+            match indicator {
+                Indicator::CapsLock => opt.caps_active ^= true,
+                _ => opt.indicators.entry(indicator).or_default().enabled ^= true,
             }
-            "Refresh" => tx.send(TrayMessage::Refresh),
-            "Quit" => tx.send(TrayMessage::Quit),
-            "QuickQuit" => std::process::exit(0),
-            _ => Ok(()),
-        } {
-            eprintln!("{e}")
         }
+        Some("Logging") => {
+            let verbosity = parse(tree.next(), id)?;
+            opt.write().unwrap().verbosity = verbosity;
+            logging::set_verbosity(verbosity);
+        }
+        Some("ImportImages") => import_images(opt.clone(), tx.clone()),
+        Some("OpenLog") => logging::open_log(&Options::get_log_path())?,
+        Some("Notice") => *NOTICE.lock().unwrap_or_else(|e| e.into_inner()) = None,
+        Some("Refresh") => tx.send(TrayMessage::Refresh)?,
+        Some("Quit") => tx.send(TrayMessage::Quit)?,
+        Some("QuickQuit") => std::process::exit(0),
+        // Items such as the about dialog are handled by the menu itself
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Make themes from the images in the themes directory, and load them straight away so they're in the menu
+/// Images can take a while to read, so this happens on its own thread rather than freezing the tray
+/// Clicking again while images are still being imported does nothing
+fn import_images(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
+    static IMPORTING: AtomicBool = AtomicBool::new(false);
+    if IMPORTING.swap(true, Ordering::SeqCst) {
+        return;
     }
 
-    proxy.send_event(EventLoopMessage::Redraw(opt.clone())).unwrap();
+    thread::spawn(move || {
+        let mut errors = Vec::new();
+        for result in crate::import_images(&Options::get_themes_path()) {
+            match result {
+                Ok(name) => log::info!("Created the theme {name}"),
+                Err(e) => errors.push(e),
+            }
+        }
+        errors.extend(opt.write().unwrap().load_user_themes());
+        IMPORTING.store(false, Ordering::SeqCst);
+
+        for e in errors {
+            log::warn!("{e}");
+            notify(&e);
+        }
+        redraw();
+        let _ = tx.send(TrayMessage::ThemeReload);
+    });
+}
+
+/// Parse part of a menu item's id
+fn parse<T: FromStr>(part: Option<&str>, id: &str) -> Result<T> {
+    part.and_then(|p| p.parse().ok()).ok_or_else(|| Error::MenuId(id.to_string()))
 }
 
-fn get_icon() -> tray_icon::Icon {
+fn get_icon() -> Result<tray_icon::Icon> {
     let rgba = include_bytes!(concat!(env!("OUT_DIR"), "/icon.rgba8"));
     let width = u32::from_be_bytes(*include_bytes!(concat!(env!("OUT_DIR"), "/icon-w")));
     let height = u32::from_be_bytes(*include_bytes!(concat!(env!("OUT_DIR"), "/icon-h")));
 
-    tray_icon::Icon::from_rgba(rgba.to_vec(), width, height).expect("Failed to load icon")
+    Ok(tray_icon::Icon::from_rgba(rgba.to_vec(), width, height)?)
 }
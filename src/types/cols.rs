@@ -1,14 +1,103 @@
+use crate::{Error, Result};
+
+use std::fmt::{self, Formatter};
+use std::ops::{Add, Mul, Not};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, EnumString};
+
+/// Chroma below which a colour counts as grey in OKLCH, where its hue is meaningless
+const ACHROMATIC: f32 = 1e-4;
+
+/// Saved as a `#rrggbb` string, but can be read from any format [Rgb::from_str] understands, or a `[r, g, b]` array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ColourRepr", into = "String")]
 pub struct Rgb(pub u8, pub u8, pub u8);
 
+/// A colour with an alpha channel, for layering over other colours
+/// Saved as a `#rrggbbaa` string, but can also be read from a `[r, g, b, a]` array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ColourRepr", into = "String")]
+pub struct Rgba {
+    pub rgb: Rgb,
+    /// How much the colour covers whatever is below it, from transparent at 0 to opaque at 255
+    pub alpha: u8,
+}
+
+/// The ways a colour can be written in a config file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColourRepr {
+    Text(String),
+    Channels(Vec<u8>),
+}
+
+/// The colour space colours are blended in, which changes what the colours in between look like
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, PartialEq, Display, EnumString, AsRefStr, EnumIter)]
+pub enum ColourSpace {
+    /// Blends the stored values directly, which is quick but makes midpoints muddy
+    #[default]
+    Srgb,
+    /// Blends light physically, which keeps midpoints bright
+    Linear,
+    /// Blends perceptually, so lightness changes evenly
+    Oklab,
+    /// Blends perceptually round the shortest way of the hue wheel, so midpoints stay saturated
+    Oklch,
+}
+
 impl Rgb {
     pub const fn from_hex(hex: u32) -> Self {
         Rgb(
-            (hex >> 16 | 0b11) as u8,
-            (hex >> 8 | 0b11) as u8,
-            (hex | 0b11) as u8,
+            (hex >> 16 & 0xff) as u8,
+            (hex >> 8 & 0xff) as u8,
+            (hex & 0xff) as u8,
         )
     }
 
+    /// Construct a colour from a hue in degrees, and a saturation and value from 0 to 1
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let (saturation, value) = (saturation.clamp(0., 1.), value.clamp(0., 1.));
+        let chroma = value * saturation;
+        Self::from_chroma(hue, chroma, value - chroma)
+    }
+
+    /// Construct a colour from a hue in degrees, and a saturation and lightness from 0 to 1
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        let (saturation, lightness) = (saturation.clamp(0., 1.), lightness.clamp(0., 1.));
+        let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+        Self::from_chroma(hue, chroma, lightness - chroma / 2.)
+    }
+
+    /// Construct a colour from lightness, a and b in the OKLab colour space
+    pub fn from_oklab([l, a, b]: [f32; 3]) -> Self {
+        let lms = [
+            l + 0.396_337_78 * a + 0.215_803_76 * b,
+            l - 0.105_561_346 * a - 0.063_854_17 * b,
+            l - 0.089_484_18 * a - 1.291_485_5 * b,
+        ]
+        .map(|c| c * c * c);
+
+        Self::from_linear([
+            4.076_741_7 * lms[0] - 3.307_711_6 * lms[1] + 0.230_969_94 * lms[2],
+            -1.268_438 * lms[0] + 2.609_757_4 * lms[1] - 0.341_319_38 * lms[2],
+            -0.004_196_086_3 * lms[0] - 0.703_418_6 * lms[1] + 1.707_614_7 * lms[2],
+        ])
+    }
+
+    /// Construct a colour from lightness, chroma and a hue in degrees in the OKLCH colour space
+    pub fn from_oklch([l, c, h]: [f32; 3]) -> Self {
+        let (sin, cos) = h.to_radians().sin_cos();
+        Self::from_oklab([l, c * cos, c * sin])
+    }
+
+    /// Construct a colour from linear RGB channels from 0 to 1
+    pub fn from_linear(linear: [f32; 3]) -> Self {
+        let [r, g, b] = linear.map(linear_to_srgb);
+        Self(r, g, b)
+    }
+
     pub fn intensify(&self, intensity: f32) -> Self {
        let intensity = intensity.clamp(0., 1.);
         Self(
@@ -30,11 +119,434 @@ impl Rgb {
         )
     }
 
-    fn to_f32_tuple(&self) -> (f32, f32, f32) {
+    /// Blend towards another colour in the given colour space, where a factor of 0 is this colour and 1 is the other
+    pub fn interpolate_in(&self, other: &Self, factor: f32, space: ColourSpace) -> Self {
+        let factor = factor.clamp(0., 1.);
+        let lerp = |a: [f32; 3], b: [f32; 3]| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * factor);
+
+        match space {
+            ColourSpace::Srgb => self.interpolate(other, factor),
+            ColourSpace::Linear => Self::from_linear(lerp(self.to_linear(), other.to_linear())),
+            ColourSpace::Oklab => Self::from_oklab(lerp(self.to_oklab(), other.to_oklab())),
+            ColourSpace::Oklch => {
+                let ([l1, c1, mut h1], [l2, c2, mut h2]) = (self.to_oklch(), other.to_oklch());
+                // Greys take on the hue of the other colour, so they don't swing round the wheel
+                if c1 < ACHROMATIC {
+                    h1 = h2;
+                } else if c2 < ACHROMATIC {
+                    h2 = h1;
+                }
+                let dh = (h2 - h1 + 540.) % 360. - 180.;
+                Self::from_oklch([l1 + (l2 - l1) * factor, c1 + (c2 - c1) * factor, h1 + dh * factor])
+            }
+        }
+    }
+
+    /// Rotate the hue by an angle in degrees, keeping the luminance roughly the same
+    /// Uses the same matrix as CSS's `hue-rotate` filter, so very saturated colours may be clipped
+    pub fn hue_rotate(self, degrees: f32) -> Self {
+        if degrees % 360. == 0. {
+            return self;
+        }
+
+        let (s, c) = degrees.to_radians().sin_cos();
+        let (r, g, b) = self.to_f32_tuple();
+        Self::from_f32(
+            (0.213 + c * 0.787 - s * 0.213) * r
+                + (0.715 - c * 0.715 - s * 0.715) * g
+                + (0.072 - c * 0.072 + s * 0.928) * b,
+            (0.213 - c * 0.213 + s * 0.143) * r
+                + (0.715 + c * 0.285 + s * 0.140) * g
+                + (0.072 - c * 0.072 - s * 0.283) * b,
+            (0.213 - c * 0.213 - s * 0.787) * r
+                + (0.715 - c * 0.715 + s * 0.715) * g
+                + (0.072 + c * 0.928 + s * 0.072) * b,
+        )
+    }
+
+    /// Relative luminance from 0 to 1, as defined by WCAG for sRGB colours
+    pub fn luminance(self) -> f32 {
+        let [r, g, b] = self.to_linear();
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// Linear RGB channels from 0 to 1, where values are proportional to the light given off
+    pub fn to_linear(self) -> [f32; 3] {
+        [self.0, self.1, self.2].map(srgb_to_linear)
+    }
+
+    /// Lightness, a and b in the OKLab colour space
+    pub fn to_oklab(self) -> [f32; 3] {
+        let [r, g, b] = self.to_linear();
+        let [l, m, s] = [
+            0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b,
+            0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b,
+            0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b,
+        ]
+        .map(f32::cbrt);
+
+        [
+            0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        ]
+    }
+
+    /// Lightness, chroma and a hue in degrees from 0 to 360 in the OKLCH colour space
+    pub fn to_oklch(self) -> [f32; 3] {
+        let [l, a, b] = self.to_oklab();
+        [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.)]
+    }
+
+    fn to_f32_tuple(self) -> (f32, f32, f32) {
         (self.0.into(), self.1.into(), self.2.into())
     }
 
     fn from_f32(r: f32, g: f32, b: f32) -> Self {
         Self(r as u8, g as u8, b as u8)
     }
+
+    /// Shared by HSV and HSL, which only differ in how the chroma and the amount added to each channel are found
+    fn from_chroma(hue: f32, chroma: f32, base: f32) -> Self {
+        let h = hue.rem_euclid(360.) / 60.;
+        let x = chroma * (1. - (h % 2. - 1.).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let [r, g, b] = [r, g, b].map(|c| ((c + base) * 255.).round() as u8);
+        Self(r, g, b)
+    }
+}
+
+/// Saturates at full brightness
+impl Add for Rgb {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0), self.1.saturating_add(other.1), self.2.saturating_add(other.2))
+    }
+}
+
+/// Multiplies each channel as a fraction of full brightness, so white leaves a colour unchanged and black clears it
+impl Mul for Rgb {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let channel = |a: u8, b: u8| (u16::from(a) * u16::from(b) / 255) as u8;
+        Self(channel(self.0, other.0), channel(self.1, other.1), channel(self.2, other.2))
+    }
+}
+
+/// The inverse colour, where black becomes white
+impl Not for Rgb {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0, !self.1, !self.2)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(rgb: Rgb) -> Self {
+        rgb.to_string()
+    }
+}
+
+/// Parses `#rgb`, `#rrggbb`, `rgb(r, g, b)` and `hsl(h, s%, l%)`
+impl FromStr for Rgb {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Rgba::from_str(s)?.opaque()
+    }
+}
+
+impl TryFrom<ColourRepr> for Rgb {
+    type Error = Error;
+
+    fn try_from(repr: ColourRepr) -> Result<Self> {
+        Rgba::try_from(repr)?.opaque()
+    }
+}
+
+impl Rgba {
+    pub const fn new(rgb: Rgb, alpha: u8) -> Self {
+        Self { rgb, alpha }
+    }
+
+    /// Layer this colour over another, blending in proportion to the alpha
+    pub fn over(self, below: Rgb) -> Rgb {
+        let alpha = f32::from(self.alpha) / 255.;
+        let channel = |b: u8, a: u8| (f32::from(b) + (f32::from(a) - f32::from(b)) * alpha).round() as u8;
+        Rgb(channel(below.0, self.rgb.0), channel(below.1, self.rgb.1), channel(below.2, self.rgb.2))
+    }
+
+    /// The colour without its alpha, as long as it's fully opaque
+    fn opaque(self) -> Result<Rgb> {
+        match self.alpha {
+            u8::MAX => Ok(self.rgb),
+            _ => Err(Error::Colour(format!("{self} can't be transparent"))),
+        }
+    }
+}
+
+impl From<Rgb> for Rgba {
+    fn from(rgb: Rgb) -> Self {
+        Self::new(rgb, u8::MAX)
+    }
+}
+
+impl fmt::Display for Rgba {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{:02x}", self.rgb, self.alpha)
+    }
+}
+
+impl From<Rgba> for String {
+    fn from(rgba: Rgba) -> Self {
+        rgba.to_string()
+    }
+}
+
+/// Parses everything [Rgb] does, along with `#rgba`, `#rrggbbaa`, `rgba(r, g, b, a)` and `hsla(h, s%, l%, a)`
+/// Alpha in a function is given from 0 to 1, or as a percentage
+impl FromStr for Rgba {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_colour(s.trim()).ok_or_else(|| Error::Colour(s.to_string()))
+    }
+}
+
+impl TryFrom<ColourRepr> for Rgba {
+    type Error = Error;
+
+    fn try_from(repr: ColourRepr) -> Result<Self> {
+        match repr {
+            ColourRepr::Text(s) => s.parse(),
+            ColourRepr::Channels(channels) => match channels[..] {
+                [r, g, b] => Ok(Rgb(r, g, b).into()),
+                [r, g, b, a] => Ok(Self::new(Rgb(r, g, b), a)),
+                _ => Err(Error::Colour(format!("{channels:?}"))),
+            },
+        }
+    }
+}
+
+fn parse_colour(s: &str) -> Option<Rgba> {
+    if let Some(hex) = s.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+
+    let (function, args) = s.strip_suffix(')')?.split_once('(')?;
+    let args: Vec<_> = args.split(',').map(str::trim).collect();
+
+    let rgb = match (function.trim().to_ascii_lowercase().as_str(), &args[..]) {
+        ("rgb", [r, g, b]) | ("rgba", [r, g, b, _]) => Rgb(r.parse().ok()?, g.parse().ok()?, b.parse().ok()?),
+        ("hsl", [h, s, l]) | ("hsla", [h, s, l, _]) => {
+            let hue: f32 = h.trim_end_matches("deg").parse().ok()?;
+            Rgb::from_hsl(hue, parse_fraction(s)?, parse_fraction(l)?)
+        }
+        _ => return None,
+    };
+
+    let alpha = match args.get(3) {
+        Some(alpha) => (parse_fraction(alpha)?.clamp(0., 1.) * 255.).round() as u8,
+        None => u8::MAX,
+    };
+    Some(Rgba::new(rgb, alpha))
+}
+
+/// Parses a shorthand or full hex colour, with or without alpha
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    let digits = hex.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect::<Option<Vec<_>>>()?;
+
+    let channels: Vec<u8> = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d * 17).collect(),
+        6 | 8 => digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect(),
+        _ => return None,
+    };
+    let alpha = channels.get(3).copied().unwrap_or(u8::MAX);
+    Some(Rgba::new(Rgb(channels[0], channels[1], channels[2]), alpha))
+}
+
+/// Parses a percentage, or a plain number from 0 to 1
+fn parse_fraction(s: &str) -> Option<f32> {
+    match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().ok().map(|p| p / 100.),
+        None => s.parse().ok(),
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = f32::from(c) / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0., 1.);
+    let c = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 };
+    (c * 255.).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use strum::IntoEnumIterator;
+
+    const RED: Rgb = Rgb(255, 0, 0);
+    const GREEN: Rgb = Rgb(0, 255, 0);
+    const BLUE: Rgb = Rgb(0, 0, 255);
+    const WHITE: Rgb = Rgb(255, 255, 255);
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3), "{a:?} != {b:?}")
+    }
+
+    #[test]
+    fn from_hex() {
+        assert_eq!(Rgb::from_hex(0x000000), Rgb(0, 0, 0));
+        assert_eq!(Rgb::from_hex(0x123456), Rgb(0x12, 0x34, 0x56));
+        assert_eq!(Rgb::from_hex(0xffffff), WHITE);
+        // Anything above 24 bits is ignored
+        assert_eq!(Rgb::from_hex(0xff00ff00), GREEN)
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!("#ff0000".parse().ok(), Some(RED));
+        assert_eq!("#F00".parse().ok(), Some(RED));
+        assert_eq!(" #123456 ".parse().ok(), Some(Rgb(0x12, 0x34, 0x56)));
+        assert_eq!("rgb(0, 255, 0)".parse().ok(), Some(GREEN));
+        assert_eq!("RGB(0,0,255)".parse().ok(), Some(BLUE));
+        assert_eq!("hsl(210, 50%, 50%)".parse().ok(), Some(Rgb(64, 128, 191)));
+        assert_eq!("hsl(120deg, 1, 0.5)".parse().ok(), Some(GREEN));
+
+        assert_eq!("#ff000080".parse().ok(), Some(Rgba::new(RED, 128)));
+        assert_eq!("#f008".parse().ok(), Some(Rgba::new(RED, 136)));
+        assert_eq!("rgba(255, 0, 0, 0.5)".parse().ok(), Some(Rgba::new(RED, 128)));
+        assert_eq!("hsla(0, 100%, 50%, 25%)".parse().ok(), Some(Rgba::new(RED, 64)));
+        assert_eq!("rgb(255, 0, 0)".parse().ok(), Some(Rgba::from(RED)));
+
+        for invalid in ["", "red", "#ff000", "#gg0000", "rgb(256, 0, 0)", "rgb(1, 2)", "rgb(1, 2, 3", "hsl(a, 1, 1)"] {
+            assert!(invalid.parse::<Rgba>().is_err(), "{invalid}")
+        }
+        // Plain colours can't be transparent
+        assert!("#ff000080".parse::<Rgb>().is_err());
+        assert!("rgba(255, 0, 0, 0.5)".parse::<Rgb>().is_err())
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for rgb in [RED, WHITE, Rgb(0, 0, 0), Rgb(1, 22, 254)] {
+            assert_eq!(rgb.to_string().parse().ok(), Some(rgb));
+            let rgba = Rgba::new(rgb, 7);
+            assert_eq!(rgba.to_string().parse().ok(), Some(rgba))
+        }
+        assert_eq!(Rgb(1, 22, 254).to_string(), "#0116fe");
+        assert_eq!(Rgba::new(RED, 128).to_string(), "#ff000080")
+    }
+
+    #[test]
+    fn serde() {
+        assert_eq!(serde_json::to_string(&RED).unwrap(), r##""#ff0000""##);
+        assert_eq!(serde_json::to_string(&Rgba::new(RED, 0)).unwrap(), r##""#ff000000""##);
+
+        assert_eq!(serde_json::from_str::<Rgb>(r##""#ff0000""##).unwrap(), RED);
+        assert_eq!(serde_json::from_str::<Rgb>(r#""hsl(0, 100%, 50%)""#).unwrap(), RED);
+        // Colours saved as arrays by earlier versions can still be read
+        assert_eq!(serde_json::from_str::<Rgb>("[255, 0, 0]").unwrap(), RED);
+        assert_eq!(serde_json::from_str::<Rgba>("[255, 0, 0]").unwrap(), RED.into());
+        assert_eq!(serde_json::from_str::<Rgba>("[255, 0, 0, 9]").unwrap(), Rgba::new(RED, 9));
+
+        assert!(serde_json::from_str::<Rgb>("[255, 0]").is_err());
+        assert!(serde_json::from_str::<Rgb>("[255, 0, 0, 9]").is_err());
+        assert!(serde_json::from_str::<Rgb>(r#""blue""#).is_err())
+    }
+
+    #[test]
+    fn blend_ops() {
+        let (a, b) = (Rgb(200, 100, 0), Rgb(100, 255, 0));
+        assert_eq!(a + b, Rgb(255, 255, 0));
+        assert_eq!(a * b, Rgb(78, 100, 0));
+        assert_eq!(a * WHITE, a);
+        assert_eq!(a * Rgb(0, 0, 0), Rgb(0, 0, 0));
+        assert_eq!(!a, Rgb(55, 155, 255));
+        assert_eq!(!!a, a)
+    }
+
+    #[test]
+    fn alpha() {
+        let below = Rgb(0, 100, 200);
+        assert_eq!(Rgba::new(RED, 255).over(below), RED);
+        assert_eq!(Rgba::new(RED, 0).over(below), below);
+        assert_eq!(Rgba::new(RED, 128).over(below), Rgb(128, 50, 100))
+    }
+
+    #[test]
+    fn hsv_and_hsl() {
+        assert_eq!(Rgb::from_hsv(0., 1., 1.), RED);
+        assert_eq!(Rgb::from_hsv(120., 1., 1.), GREEN);
+        assert_eq!(Rgb::from_hsv(-120., 1., 0.5), Rgb(0, 0, 128));
+        assert_eq!(Rgb::from_hsv(0., 0., 1.), WHITE);
+
+        assert_eq!(Rgb::from_hsl(0., 1., 0.5), RED);
+        assert_eq!(Rgb::from_hsl(210., 0.5, 0.5), Rgb(64, 128, 191));
+        assert_eq!(Rgb::from_hsl(60., 1., 1.), WHITE)
+    }
+
+    #[test]
+    fn oklab_vectors() {
+        // Reference values from the OKLab definition
+        assert_close(WHITE.to_oklab(), [1., 0., 0.]);
+        assert_close(RED.to_oklab(), [0.627_955, 0.224_863, 0.125_846]);
+        assert_close(GREEN.to_oklab(), [0.866_440, -0.233_888, 0.179_498]);
+        assert_close(BLUE.to_oklab(), [0.452_014, -0.032_457, -0.311_528]);
+        assert_close(RED.to_oklch(), [0.627_955, 0.257_683, 29.233_885])
+    }
+
+    #[test]
+    fn round_trips() {
+        for rgb in [RED, GREEN, BLUE, WHITE, Rgb(0, 0, 0), Rgb(12, 200, 99), Rgb(255, 0, 128)] {
+            assert_eq!(Rgb::from_linear(rgb.to_linear()), rgb);
+            assert_eq!(Rgb::from_oklab(rgb.to_oklab()), rgb);
+            assert_eq!(Rgb::from_oklch(rgb.to_oklch()), rgb);
+            assert_eq!(rgb.hue_rotate(360.), rgb)
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        for space in ColourSpace::iter() {
+            assert_eq!(RED.interpolate_in(&BLUE, 0., space), RED, "{space}");
+            assert_eq!(RED.interpolate_in(&BLUE, 1., space), BLUE, "{space}")
+        }
+
+        // The sRGB midpoint of green and blue is dull, and the others are all brighter
+        let dull = GREEN.interpolate_in(&BLUE, 0.5, ColourSpace::Srgb).luminance();
+        for space in [ColourSpace::Linear, ColourSpace::Oklab, ColourSpace::Oklch] {
+            assert!(GREEN.interpolate_in(&BLUE, 0.5, space).luminance() > dull, "{space}")
+        }
+
+        // OKLCH goes round the hue wheel, rather than through grey
+        let [_, chroma, _] = RED.interpolate_in(&Rgb(0, 255, 255), 0.5, ColourSpace::Oklch).to_oklch();
+        assert!(chroma > 0.1);
+        assert_eq!(WHITE.interpolate_in(&RED, 1., ColourSpace::Oklch), RED)
+    }
 }
@@ -0,0 +1,89 @@
+use crate::Rgb;
+
+use serde::{Deserialize, Serialize};
+
+/// How a particular keyboard's LEDs respond to the values written to them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Calibration {
+    /// LEDs are roughly linear, so values are raised to this power to make intensities look even
+    /// A gamma of 1 leaves colours as the theme outputs them
+    pub gamma: f32,
+    /// Multipliers for the red, green and blue channels, for correcting a tinted white
+    pub white_balance: [f32; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self { gamma: 1., white_balance: [1.; 3] }
+    }
+}
+
+/// Lookup tables mapping the colours themes output to the values written to the LEDs
+/// Outputs are kept fractional, so they can be dithered rather than rounded
+pub struct Correction {
+    lut: [[f32; 256]; 3],
+}
+
+impl Correction {
+    /// `brightness` caps the output of every channel, from 0 to 1
+    pub fn new(brightness: f32, calibration: Calibration) -> Self {
+        let brightness = brightness.clamp(0., 1.);
+        let mut lut = [[0.; 256]; 3];
+
+        for (table, balance) in lut.iter_mut().zip(calibration.white_balance) {
+            let scale = brightness * balance.clamp(0., 1.);
+            for (i, out) in table.iter_mut().enumerate() {
+                *out = (i as f32 / 255.).powf(calibration.gamma) * scale * 255.;
+            }
+        }
+
+        Self { lut }
+    }
+
+    pub fn apply(&self, rgb: Rgb) -> Rgb {
+        let [r, g, b] = self.apply_exact(rgb).map(|c| c.round() as u8);
+        Rgb(r, g, b)
+    }
+
+    /// The corrected channels before they're rounded, from 0 to 255
+    pub fn apply_exact(&self, rgb: Rgb) -> [f32; 3] {
+        [self.lut[0][usize::from(rgb.0)], self.lut[1][usize::from(rgb.1)], self.lut[2][usize::from(rgb.2)]]
+    }
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self::new(1., Calibration::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity() {
+        let correction = Correction::default();
+        for v in 0..=255 {
+            assert_eq!(correction.apply(Rgb(v, v, v)), Rgb(v, v, v))
+        }
+    }
+
+    #[test]
+    fn gamma() {
+        let correction = Correction::new(1., Calibration { gamma: 2.2, ..Default::default() });
+        assert_eq!(correction.apply(Rgb(0, 128, 255)), Rgb(0, 56, 255))
+    }
+
+    #[test]
+    fn brightness_cap() {
+        let correction = Correction::new(0.5, Calibration::default());
+        assert_eq!(correction.apply(Rgb(255, 100, 0)), Rgb(128, 50, 0))
+    }
+
+    #[test]
+    fn white_balance() {
+        let correction = Correction::new(1., Calibration { white_balance: [1., 0.8, 0.5], ..Default::default() });
+        assert_eq!(correction.apply(Rgb(255, 255, 255)), Rgb(255, 204, 128))
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Rgb;
+
+use std::collections::HashMap;
+
+/// Spreads the rounding error of each key over the following frames, so a fractional level is shown by alternating
+/// between the two nearest values
+/// Low intensities only have a handful of 8-bit steps, especially after gamma correction, so fades would step visibly
+#[derive(Default)]
+pub struct TemporalDither {
+    /// Error carried over from the previous frame for each channel of each key
+    error: HashMap<(u8, u8), [f32; 3]>,
+    /// Error carried over from the previous frame for the partly lit key of each bar, in 8-bit steps
+    height_error: HashMap<(u8, u8), f32>,
+}
+
+impl TemporalDither {
+    /// Round how far a bar reaches into the key at `row` to a whole 8-bit step, carrying the error into its next frame
+    /// Themes return 8-bit colours, so the fraction of a step would otherwise be lost before [Self::quantise]
+    /// The result lands halfway through the step, so a fully saturated channel is truncated to exactly that step
+    pub fn bar_height(&mut self, point: (u8, u8), row: u8, bar_height: f32) -> f32 {
+        let intensity = bar_height - f32::from(row);
+        if !(0. ..1.).contains(&intensity) {
+            self.height_error.remove(&point);
+            return bar_height;
+        }
+
+        let error = self.height_error.entry(point).or_default();
+        let target = intensity * 255. + *error;
+        let step = target.round().clamp(0., 255.);
+        *error = (target - step).clamp(-1., 1.);
+
+        f32::from(row) + (step + 0.5) / 255.
+    }
+
+    /// Round a key's exact colour, with channels from 0 to 255, carrying the error into its next frame
+    pub fn quantise(&mut self, coord: (u8, u8), exact: [f32; 3]) -> Rgb {
+        let error = self.error.entry(coord).or_default();
+        let mut out = [0; 3];
+
+        for ((out, exact), error) in out.iter_mut().zip(exact).zip(error.iter_mut()) {
+            let target = exact + *error;
+            *out = target.round().clamp(0., 255.) as u8;
+            // Clamped so a sudden change doesn't leave a large debt to pay off over many frames
+            *error = (target - f32::from(*out)).clamp(-1., 1.);
+        }
+
+        Rgb(out[0], out[1], out[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average red value of a key held at `level` for a number of frames
+    fn average(level: f32, frames: u32) -> f32 {
+        let mut dither = TemporalDither::default();
+        let total: u32 = (0..frames).map(|_| u32::from(dither.quantise((0, 0), [level, 0., 0.]).0)).sum();
+        total as f32 / frames as f32
+    }
+
+    #[test]
+    fn fractional_average() {
+        assert_eq!(average(0.25, 4), 0.25);
+        assert!((average(10.4, 100) - 10.4).abs() < 0.02)
+    }
+
+    #[test]
+    fn whole_values_steady() {
+        let mut dither = TemporalDither::default();
+        for _ in 0..10 {
+            assert_eq!(dither.quantise((0, 0), [0., 128., 255.]), Rgb(0, 128, 255))
+        }
+    }
+
+    #[test]
+    fn bar_height_steps() {
+        let mut dither = TemporalDither::default();
+        let red = |height: f32| (255. * (height - 2.).clamp(0., 1.)) as u8;
+
+        // Keys that are fully lit or unlit aren't changed
+        assert_eq!(dither.bar_height((0, 0), 1, 2.5), 2.5);
+        assert_eq!(dither.bar_height((0, 0), 3, 2.5), 2.5);
+
+        // A quarter of the way between two steps is shown by the upper step one frame in four
+        let level = 2. + 100.25 / 255.;
+        let steps: Vec<u8> = (0..4).map(|_| red(dither.bar_height((0, 0), 2, level))).collect();
+        assert_eq!(steps.iter().filter(|s| **s == 101).count(), 1);
+        assert_eq!(steps.iter().filter(|s| **s == 100).count(), 3)
+    }
+
+    #[test]
+    fn keys_independent() {
+        let mut dither = TemporalDither::default();
+        dither.quantise((0, 0), [0.5, 0., 0.]);
+        assert_eq!(dither.quantise((0, 1), [0.4, 0., 0.]), Rgb(0, 0, 0))
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Everything that can go wrong while the app is running
+#[derive(Debug)]
+pub enum Error {
+    // Audio
+    /// There's no audio device to listen to
+    NoAudioDevice,
+    Devices(cpal::DevicesError),
+    StreamConfig(cpal::DefaultStreamConfigError),
+    BuildStream(cpal::BuildStreamError),
+    PlayStream(cpal::PlayStreamError),
+    /// The stream failed while it was running
+    Stream(cpal::BackendSpecificError),
+    UnsupportedFormat(cpal::SampleFormat),
+
+    // Device
+    /// Another copy of the app couldn't be checked for, as it would be fighting over the keyboard
+    Instance(single_instance::error::Error),
+
+    // Config
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// The log file couldn't be written or opened
+    Log(std::io::Error),
+    /// A theme file couldn't be understood
+    Theme(std::path::PathBuf, serde_json::Error),
+    /// A theme file or the themes directory couldn't be read or written
+    ThemeIo(std::path::PathBuf, std::io::Error),
+    /// A script theme couldn't be compiled, or failed when it was tried out
+    Script(std::path::PathBuf, Box<rhai::EvalAltResult>),
+    /// A colour was written in a way that couldn't be understood
+    Colour(String),
+    /// An image couldn't be read to make a theme from
+    Image(std::path::PathBuf, image::ImageError),
+
+    // Tray
+    Menu(tray_icon::menu::Error),
+    TrayIcon(tray_icon::Error),
+    Icon(tray_icon::BadIcon),
+    EventLoop(winit::error::EventLoopError),
+    /// A menu item had an id that couldn't be understood
+    MenuId(String),
+    /// The main loop has stopped listening to the tray
+    Disconnected,
+}
+
+/// How the app should carry on after an [Error]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    /// Wait a moment and try again, as the problem may be temporary
+    Retry,
+    /// Switch back to the default audio device, as the selected one can't be used
+    DefaultDevice,
+    /// Tell the user, as it's not something that will sort itself out
+    Notify,
+}
+
+impl Error {
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            Self::NoAudioDevice | Self::Devices(_) | Self::PlayStream(_) | Self::Stream(_) => Recovery::Retry,
+            Self::StreamConfig(_) | Self::BuildStream(_) | Self::UnsupportedFormat(_) => Recovery::DefaultDevice,
+            Self::Instance(_)
+            | Self::Io(_)
+            | Self::Json(_)
+            | Self::Log(_)
+            | Self::Theme(_, _)
+            | Self::ThemeIo(_, _)
+            | Self::Script(_, _)
+            | Self::Colour(_)
+            | Self::Image(_, _)
+            | Self::Menu(_)
+            | Self::TrayIcon(_)
+            | Self::Icon(_)
+            | Self::EventLoop(_)
+            | Self::MenuId(_)
+            | Self::Disconnected => Recovery::Notify,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::NoAudioDevice => write!(f, "No audio device found"),
+            Self::Devices(e) => write!(f, "Couldn't list audio devices: {e}"),
+            Self::StreamConfig(e) => write!(f, "Couldn't get the audio device's config: {e}"),
+            Self::BuildStream(e) => write!(f, "Couldn't open the audio device: {e}"),
+            Self::PlayStream(e) => write!(f, "Couldn't start listening to the audio device: {e}"),
+            Self::Stream(e) => write!(f, "Audio stream failed: {e}"),
+            Self::UnsupportedFormat(format) => write!(f, "Unsupported audio format: {format}"),
+            Self::Instance(e) => write!(f, "Couldn't check whether the app is already running: {e}"),
+            Self::Io(e) => write!(f, "Couldn't access the options file: {e}"),
+            Self::Json(e) => write!(f, "Invalid options file: {e}"),
+            Self::Log(e) => write!(f, "Couldn't access the log file: {e}"),
+            Self::Theme(path, e) => write!(f, "Couldn't load the theme {}: {e}", path.display()),
+            Self::ThemeIo(path, e) => write!(f, "Couldn't access the theme {}: {e}", path.display()),
+            Self::Script(path, e) => write!(f, "Couldn't run the script {}: {e}", path.display()),
+            Self::Colour(colour) => write!(f, "Invalid colour: {colour}"),
+            Self::Image(path, e) => write!(f, "Couldn't read the image {}: {e}", path.display()),
+            Self::Menu(e) => write!(f, "Couldn't build the tray menu: {e}"),
+            Self::TrayIcon(e) => write!(f, "Couldn't create the tray icon: {e}"),
+            Self::Icon(e) => write!(f, "Couldn't load the tray icon: {e}"),
+            Self::EventLoop(e) => write!(f, "Tray event loop failed: {e}"),
+            Self::MenuId(id) => write!(f, "Unknown menu item: {id}"),
+            Self::Disconnected => write!(f, "The app has stopped responding to the tray"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+macro_rules! impl_from {
+    ( $( $variant:ident($from:ty) ),* ) => {
+        $(
+            impl From<$from> for Error {
+                fn from(e: $from) -> Self {
+                    Self::$variant(e)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    Devices(cpal::DevicesError),
+    StreamConfig(cpal::DefaultStreamConfigError),
+    BuildStream(cpal::BuildStreamError),
+    PlayStream(cpal::PlayStreamError),
+    Instance(single_instance::error::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Menu(tray_icon::menu::Error),
+    TrayIcon(tray_icon::Error),
+    Icon(tray_icon::BadIcon),
+    EventLoop(winit::error::EventLoopError)
+);
+
+impl<T> From<std::sync::mpsc::SendError<T>> for Error {
+    fn from(_: std::sync::mpsc::SendError<T>) -> Self {
+        Self::Disconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery() {
+        assert_eq!(Error::NoAudioDevice.recovery(), Recovery::Retry);
+        assert_eq!(Error::UnsupportedFormat(cpal::SampleFormat::I8).recovery(), Recovery::DefaultDevice);
+        assert_eq!(Error::MenuId(String::from("Themes:Unknown")).recovery(), Recovery::Notify)
+    }
+
+    #[test]
+    fn from_send_error() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        drop(rx);
+        assert!(matches!(Error::from(tx.send(()).unwrap_err()), Error::Disconnected))
+    }
+}
@@ -0,0 +1,255 @@
+use crate::Rgb;
+
+use std::collections::{HashMap, VecDeque};
+
+/// Change in relative luminance that counts towards a flash, as in WCAG 2.3.1
+const FLASH_THRESHOLD: f32 = 0.1;
+/// Changes are only a flash if the darker side is darker than this
+const DARK_LIMIT: f32 = 0.8;
+/// Transitions allowed in any window, where two opposing transitions make up one flash
+/// WCAG allows three flashes a second
+const MAX_TRANSITIONS: usize = 6;
+/// Steps taken when searching for the largest transition that's still allowed
+const SEARCH_STEPS: u32 = 10;
+
+/// Follows the luminance of a key (or the whole board), counting the transitions that would make up a flash
+/// A steady fade in one direction only counts once, as transitions are measured from the furthest point reached
+#[derive(Default)]
+struct Tracker {
+    /// The brightest or darkest luminance reached since the last transition
+    extreme: f32,
+    /// Whether luminance has been rising (1) or falling (-1), or 0 before the first transition
+    direction: i8,
+    /// Frames that transitions happened on
+    transitions: VecDeque<u64>,
+}
+
+impl Tracker {
+    /// Whether moving to `luminance` would count as a transition
+    fn is_transition(&self, luminance: f32) -> bool {
+        let delta = luminance - self.extreme;
+        let reversing = match self.direction {
+            1 => delta < 0.,
+            -1 => delta > 0.,
+            _ => true,
+        };
+
+        reversing && delta.abs() >= FLASH_THRESHOLD && luminance.min(self.extreme) < DARK_LIMIT
+    }
+
+    /// Whether another transition would exceed the limit in the window ending on `frame`
+    fn saturated(&mut self, frame: u64, window: u64) -> bool {
+        while self.transitions.front().is_some_and(|f| *f + window <= frame) {
+            self.transitions.pop_front();
+        }
+        self.transitions.len() >= MAX_TRANSITIONS
+    }
+
+    fn record(&mut self, luminance: f32, frame: u64) {
+        if self.is_transition(luminance) {
+            self.direction = if luminance > self.extreme { 1 } else { -1 };
+            self.extreme = luminance;
+            self.transitions.push_back(frame);
+        } else if (self.direction == 1 && luminance > self.extreme)
+            || (self.direction == -1 && luminance < self.extreme)
+        {
+            self.extreme = luminance;
+        }
+    }
+}
+
+/// Limits how often each key, and the board as a whole, can flash
+/// Once the limit is reached, transitions that would be another flash are softened until the window moves on
+pub struct FlashLimiter {
+    /// Length of the window that flashes are counted over, in frames
+    window: u64,
+    frame: u64,
+    keys: HashMap<(u8, u8), Tracker>,
+    board: Tracker,
+    /// The colours output on the last frame
+    previous: HashMap<(u8, u8), Rgb>,
+}
+
+impl FlashLimiter {
+    /// `fps` is the rate that frames are passed to [FlashLimiter::limit], so flashes are counted over one second
+    pub fn new(fps: u32) -> Self {
+        Self {
+            window: u64::from(fps.max(1)),
+            frame: 0,
+            keys: HashMap::new(),
+            board: Tracker::default(),
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Get the colours to display this frame, softening any change that would flash too often
+    pub fn limit(&mut self, colours: &HashMap<(u8, u8), Rgb>) -> HashMap<(u8, u8), Rgb> {
+        let (frame, window) = (self.frame, self.window);
+        self.frame += 1;
+
+        // Each key on its own
+        let mut out: HashMap<_, _> = colours
+            .iter()
+            .map(|(coord, target)| {
+                let previous = self.previous.get(coord).copied().unwrap_or(Rgb(0, 0, 0));
+                let tracker = self.keys.entry(*coord).or_default();
+
+                let rgb = if tracker.saturated(frame, window) && tracker.is_transition(target.luminance()) {
+                    let factor =
+                        largest_allowed(|f| tracker.is_transition(previous.interpolate(target, f).luminance()));
+                    previous.interpolate(target, factor)
+                } else {
+                    *target
+                };
+
+                (*coord, rgb)
+            })
+            .collect();
+
+        // The board as a whole, softening every key by the same amount
+        if self.board.saturated(frame, window) && self.board.is_transition(mean_luminance(&out)) {
+            let blended = |f: f32| -> HashMap<_, _> {
+                out.iter()
+                    .map(|(coord, rgb)| {
+                        let previous = self.previous.get(coord).copied().unwrap_or(Rgb(0, 0, 0));
+                        (*coord, previous.interpolate(rgb, f))
+                    })
+                    .collect()
+            };
+            let factor = largest_allowed(|f| self.board.is_transition(mean_luminance(&blended(f))));
+            out = blended(factor);
+        }
+
+        self.board.record(mean_luminance(&out), frame);
+        for (coord, rgb) in out.iter() {
+            self.keys.entry(*coord).or_default().record(rgb.luminance(), frame);
+        }
+
+        self.previous = out.clone();
+        out
+    }
+}
+
+/// Search for the largest interpolation factor between the previous and target colours that isn't a transition
+/// A factor of 0 (staying the same) is always allowed
+fn largest_allowed(is_transition: impl Fn(f32) -> bool) -> f32 {
+    let (mut allowed, mut disallowed) = (0., 1.);
+    for _ in 0..SEARCH_STEPS {
+        let mid = (allowed + disallowed) / 2.;
+        if is_transition(mid) {
+            disallowed = mid;
+        } else {
+            allowed = mid;
+        }
+    }
+    allowed
+}
+
+fn mean_luminance(colours: &HashMap<(u8, u8), Rgb>) -> f32 {
+    colours.values().map(|rgb| rgb.luminance()).sum::<f32>() / colours.len().max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPS: u32 = 60;
+    const WHITE: Rgb = Rgb(255, 255, 255);
+    const BLACK: Rgb = Rgb(0, 0, 0);
+
+    /// Count the flashes in a sequence of luminances, each of which is a pair of opposing transitions
+    fn flashes(luminances: &[f32]) -> usize {
+        let mut tracker = Tracker { extreme: luminances[0], ..Default::default() };
+        for (frame, l) in luminances.iter().enumerate() {
+            tracker.record(*l, frame as u64);
+        }
+        tracker.transitions.len() / 2
+    }
+
+    /// Feed a board that alternates between two colours every `period` frames, returning the mean luminance output
+    fn strobe(limiter: &mut FlashLimiter, keys: u8, frames: usize, period: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let rgb = if (i / period) % 2 == 0 { WHITE } else { BLACK };
+                mean_luminance(&limiter.limit(&board(keys, rgb)))
+            })
+            .collect()
+    }
+
+    fn board(keys: u8, rgb: Rgb) -> HashMap<(u8, u8), Rgb> {
+        (0..keys).map(|col| ((0, col), rgb)).collect()
+    }
+
+    #[test]
+    fn fade_counts_once() {
+        // Fading up and back down is one pair of transitions, however many frames it's spread over
+        let fade: Vec<f32> = (0..=100).chain((0..=100).rev()).map(|i| i as f32 / 100.).collect();
+        assert_eq!(flashes(&fade), 1)
+    }
+
+    #[test]
+    fn bright_flicker_ignored() {
+        assert_eq!(flashes(&[0.85, 1., 0.85, 1., 0.85]), 0)
+    }
+
+    #[test]
+    fn slow_strobe_untouched() {
+        // Two flashes a second is within the limit
+        let mut limiter = FlashLimiter::new(FPS);
+        let out = strobe(&mut limiter, 4, 120, 15);
+        assert!(out.iter().all(|l| *l == 0. || *l == 1.))
+    }
+
+    #[test]
+    fn fast_strobe_limited() {
+        // Ten flashes a second
+        let mut limiter = FlashLimiter::new(FPS);
+        let out = strobe(&mut limiter, 4, 600, 3);
+        assert!(out.contains(&1.));
+
+        for second in out.windows(FPS as usize) {
+            assert!(flashes(second) <= 3, "{second:?}")
+        }
+    }
+
+    #[test]
+    fn single_key_limited() {
+        // Only one key strobes, which is too small to be a board flash, but is still limited on its own
+        let mut limiter = FlashLimiter::new(FPS);
+        let mut on = board(20, BLACK);
+        on.insert((0, 0), WHITE);
+
+        let off = board(20, BLACK);
+        let out: Vec<f32> =
+            (0..300).map(|i| limiter.limit(if i % 2 == 0 { &on } else { &off })[&(0, 0)].luminance()).collect();
+
+        for second in out.windows(FPS as usize) {
+            assert!(flashes(second) <= 3, "{second:?}")
+        }
+    }
+
+    #[test]
+    fn board_limited_across_keys() {
+        // Keys take turns flashing, so no key flashes too often but the board does
+        let mut limiter = FlashLimiter::new(FPS);
+        let frames: Vec<_> = (0..4u8)
+            .map(|k| {
+                let mut frame = board(4, BLACK);
+                frame.insert((0, k), WHITE);
+                frame
+            })
+            .collect();
+
+        let out: Vec<f32> = (0..600)
+            .map(|i| {
+                // Every key lit, then each key's own turn with the others dark
+                let frame = if i % 2 == 0 { board(4, WHITE) } else { frames[(i / 2) % 4].clone() };
+                mean_luminance(&limiter.limit(&frame))
+            })
+            .collect();
+
+        for second in out.windows(FPS as usize) {
+            assert!(flashes(second) <= 3, "{second:?}")
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::Rgb;
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// Counters describing how many USB writes were avoided by frame diffing
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    /// Frames that were flushed to the keyboard
+    pub frames_sent: u64,
+    /// Frames that were identical to the previous one, so the update was skipped entirely
+    pub frames_skipped: u64,
+    /// Individual keys that were written
+    pub keys_sent: u64,
+    /// Individual keys that were left alone because their colour didn't change
+    pub keys_skipped: u64,
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let frames = self.frames_sent + self.frames_skipped;
+        let keys = self.keys_sent + self.keys_skipped;
+        write!(f, "skipped {} of {frames} frames and {} of {keys} key writes", self.frames_skipped, self.keys_skipped)
+    }
+}
+
+/// Buffers the colours of a frame in absolute (row, col) coordinates, and remembers the last frame sent
+/// Only keys that differ from what the keyboard is already displaying are returned to be written
+#[derive(Default)]
+pub struct FrameDiff {
+    pending: HashMap<(u8, u8), Rgb>,
+    sent: HashMap<(u8, u8), Rgb>,
+    stats: FrameStats,
+}
+
+impl FrameDiff {
+    /// Set the colour of a key in the frame being built, overwriting any colour already set this frame
+    pub fn set(&mut self, coord: (u8, u8), rgb: Rgb) {
+        self.pending.insert(coord, rgb);
+    }
+
+    /// Finish the current frame, returning only the keys that need to be written
+    /// An empty result means the keyboard doesn't need updating at all
+    pub fn flush(&mut self) -> Vec<((u8, u8), Rgb)> {
+        let mut changed = Vec::new();
+
+        for (coord, rgb) in self.pending.drain() {
+            if self.sent.get(&coord) == Some(&rgb) {
+                self.stats.keys_skipped += 1;
+            } else {
+                self.sent.insert(coord, rgb);
+                changed.push((coord, rgb));
+            }
+        }
+
+        self.stats.keys_sent += changed.len() as u64;
+        if changed.is_empty() {
+            self.stats.frames_skipped += 1;
+        } else {
+            self.stats.frames_sent += 1;
+        }
+
+        changed
+    }
+
+    /// Every key of the last frame sent, whether or not it changed
+    pub fn current(&self) -> Vec<((u8, u8), Rgb)> {
+        self.sent.iter().map(|(coord, rgb)| (*coord, *rgb)).collect()
+    }
+
+    /// Forget the last frame sent, so every key is written on the next flush
+    pub fn invalidate(&mut self) {
+        self.sent.clear();
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_sent() {
+        let mut diff = FrameDiff::default();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        diff.set((0, 1), Rgb(1, 2, 3));
+        assert_eq!(diff.flush().len(), 2)
+    }
+
+    #[test]
+    fn identical_frame_skipped() {
+        let mut diff = FrameDiff::default();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        diff.flush();
+        diff.set((0, 0), Rgb(1, 2, 3));
+
+        assert!(diff.flush().is_empty());
+        assert_eq!(diff.stats(), FrameStats { frames_sent: 1, frames_skipped: 1, keys_sent: 1, keys_skipped: 1 })
+    }
+
+    #[test]
+    fn only_changed_keys() {
+        let mut diff = FrameDiff::default();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        diff.set((0, 1), Rgb(1, 2, 3));
+        diff.flush();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        diff.set((0, 1), Rgb(4, 5, 6));
+
+        assert!(diff.flush() == vec![((0, 1), Rgb(4, 5, 6))])
+    }
+
+    #[test]
+    fn stats_display() {
+        let stats = FrameStats { frames_sent: 3, frames_skipped: 1, keys_sent: 10, keys_skipped: 20 };
+        assert_eq!(stats.to_string(), "skipped 1 of 4 frames and 20 of 30 key writes")
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut diff = FrameDiff::default();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        diff.flush();
+        diff.invalidate();
+        diff.set((0, 0), Rgb(1, 2, 3));
+        assert_eq!(diff.flush().len(), 1)
+    }
+}
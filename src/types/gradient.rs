@@ -0,0 +1,210 @@
+use crate::themes::Theme;
+use crate::{ColourSpace, Error, Keyboard, Result, Rgb, ScriptTheme};
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// A colour at a point up the keyboard, from 0 on the bottom row to 1 on the top
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct GradientStop {
+    pub position: f32,
+    pub colour: Rgb,
+}
+
+/// A theme loaded from a JSON file in the themes directory, so palettes can be added without recompiling
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GradientTheme {
+    /// Colours up the keyboard, which are blended between
+    pub stops: Vec<GradientStop>,
+    /// How far the hue is rotated from one column to the next, in degrees
+    #[serde(default)]
+    pub hue_shift: f32,
+    /// Colour of the top key of each bar, in place of the gradient
+    #[serde(default)]
+    pub peak: Option<Rgb>,
+    /// Colour of unlit keys
+    #[serde(default = "black")]
+    pub background: Rgb,
+    /// The colour space stops are blended in
+    #[serde(default)]
+    pub interpolation: ColourSpace,
+}
+
+fn black() -> Rgb {
+    Rgb(0, 0, 0)
+}
+
+impl GradientTheme {
+    /// Read a theme file, sorting its stops so they can be given in any order
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| Error::ThemeIo(path.to_path_buf(), e))?;
+        let mut theme: Self =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| Error::Theme(path.to_path_buf(), e))?;
+
+        theme.stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Ok(theme)
+    }
+
+    /// Save a theme, creating the themes directory if it doesn't exist yet
+    /// It's written to a temporary file that's then moved into place, so the theme is never seen half written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let error = |e| Error::ThemeIo(path.to_path_buf(), e);
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(error)?
+        }
+
+        let temp = path.with_extension("json.tmp");
+        let file = File::create(&temp).map_err(error)?;
+        let written = serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .map_err(|e| Error::Theme(path.to_path_buf(), e))
+            .and_then(|_| rename(&temp, path).map_err(error));
+        if written.is_err() {
+            let _ = remove_file(&temp);
+        }
+        written
+    }
+
+    /// The colour of the gradient at a position from 0 to 1
+    fn colour_at(&self, position: f32) -> Rgb {
+        let (Some(first), Some(last)) = (self.stops.first(), self.stops.last()) else { return self.background };
+
+        if position <= first.position {
+            return first.colour;
+        }
+        for pair in self.stops.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if position <= b.position {
+                let factor =
+                    if b.position > a.position { (position - a.position) / (b.position - a.position) } else { 1. };
+                return a.colour.interpolate_in(&b.colour, factor, self.interpolation);
+            }
+        }
+        last.colour
+    }
+}
+
+impl Theme for GradientTheme {
+    fn get_led_color(&self, kbd: &Keyboard, col: u8, row: u8, bar_height: f32) -> Rgb {
+        let intensity = (bar_height - f32::from(row)).clamp(0., 1.);
+        let position = f32::from(row) / f32::from(kbd.rows().saturating_sub(1).max(1));
+        let is_peak = bar_height - f32::from(row) < 1.;
+
+        let colour = match self.peak {
+            Some(peak) if is_peak => peak,
+            _ => self.colour_at(position).hue_rotate(self.hue_shift * f32::from(col)),
+        };
+        self.background.interpolate(&colour, intensity)
+    }
+}
+
+/// A theme from the themes directory, which is either a JSON gradient or a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserTheme {
+    Gradient(GradientTheme),
+    Script(ScriptTheme),
+}
+
+impl UserTheme {
+    pub fn get_theme(&self) -> Box<dyn Theme> {
+        match self {
+            Self::Gradient(theme) => Box::new(theme.clone()),
+            Self::Script(theme) => Box::new(theme.clone()),
+        }
+    }
+}
+
+/// Load every theme in a directory by file name, including those that couldn't be loaded
+/// Names containing ':' are skipped, as they can't be told apart in a menu item's id
+/// A script takes the place of a gradient with the same name
+pub fn load_user_themes(dir: &Path) -> Result<BTreeMap<String, Result<UserTheme>>> {
+    let mut themes = BTreeMap::new();
+
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(themes),
+        Err(e) => return Err(Error::ThemeIo(dir.to_path_buf(), e)),
+    };
+
+    // Sorted so a script always comes after the gradient it replaces
+    let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()).filter(|name| !name.contains(':')) else {
+            continue;
+        };
+
+        let theme = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => GradientTheme::read(&path).map(UserTheme::Gradient),
+            Some("rhai") => ScriptTheme::read(&path).map(UserTheme::Script),
+            _ => continue,
+        };
+        themes.insert(name.to_string(), theme);
+    }
+
+    Ok(themes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    fn theme() -> GradientTheme {
+        serde_json::from_str(
+            r#"{ "stops": [
+                { "position": 0.0, "colour": [0, 0, 255] },
+                { "position": 0.5, "colour": [0, 255, 0] },
+                { "position": 1.0, "colour": [255, 0, 0] }
+            ] }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let theme = theme();
+        assert_eq!(theme.hue_shift, 0.);
+        assert_eq!(theme.peak, None);
+        assert_eq!(theme.background, Rgb(0, 0, 0));
+        assert_eq!(theme.interpolation, ColourSpace::Srgb)
+    }
+
+    #[test]
+    fn stops() {
+        let theme = theme();
+        assert_eq!(theme.colour_at(-1.), Rgb(0, 0, 255));
+        assert_eq!(theme.colour_at(0.5), Rgb(0, 255, 0));
+        assert_eq!(theme.colour_at(0.75), Rgb(127, 127, 0));
+        assert_eq!(theme.colour_at(2.), Rgb(255, 0, 0));
+        assert_eq!(GradientTheme { stops: vec![], ..theme }.colour_at(0.5), Rgb(0, 0, 0))
+    }
+
+    #[test]
+    fn loads_directory() {
+        let dir = TempDir::new("themes");
+
+        // Stops out of order are sorted
+        let stops = r#"{ "stops": [{ "position": 1, "colour": [1, 1, 1] }, { "position": 0, "colour": [0, 0, 0] }] }"#;
+        std::fs::write(dir.join("Sunset.json"), stops).unwrap();
+        std::fs::write(dir.join("Broken.json"), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let themes = load_user_themes(&dir).unwrap();
+        assert_eq!(themes.keys().collect::<Vec<_>>(), ["Broken", "Sunset"]);
+        assert!(matches!(themes["Broken"], Err(Error::Theme(_, _))));
+        assert!(matches!(&themes["Sunset"], Ok(UserTheme::Gradient(theme)) if theme.stops[0].position == 0.));
+
+        // A script replaces a gradient with the same name
+        std::fs::write(dir.join("Sunset.rhai"), "fn colour(a, b, c, d, e, f, g) { rgb(0, 0, 0) }").unwrap();
+        assert!(matches!(load_user_themes(&dir).unwrap()["Sunset"], Ok(UserTheme::Script(_))));
+
+        let missing = dir.join("missing");
+        assert!(load_user_themes(&missing).unwrap().is_empty());
+        assert!(matches!(GradientTheme::read(&missing.join("Gone.json")), Err(Error::ThemeIo(_, _))))
+    }
+}
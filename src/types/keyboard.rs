@@ -1,40 +1,22 @@
 use crate::*;
-use themes::Theme;
+use themes::{Crossfade, FrameContext, Theme, ThemeSource};
 
+use std::collections::HashMap;
 use std::mem::ManuallyDrop;
 use std::result::Result;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use wooting_rgb::{Key, RgbKeyboard};
-use wooting_rgb_sys::WOOTING_USB_META;
+use wooting_rgb::RgbKeyboard;
+use wooting_rgb_sys::{wooting_rgb_reset_rgb, wooting_usb_select_device, WOOTING_USB_META};
 use wooting_rgb_sys::{
     WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD, WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD_60,
     WOOTING_DEVICE_TYPE_DEVICE_KEYBOARD_TKL, WOOTING_DEVICE_TYPE_DEVICE_KEYPAD_3KEY,
 };
 
-/// Coordinate system for the keyboard where (0,0) is L-Ctrl
-/// The ordering is (x, y)
-struct KeyboardCoord(u8, u8);
-
-impl KeyboardCoord {
-    pub fn to_absolute(&self, rows: u8, top_row: u8) -> (u8, u8) {
-        let row = rows - 1 - self.1 + top_row;
-        let coord = (self.0, row);
-        // Correction for shift key
-        if coord == (13, 4) {
-            (12, 4)
-        } else if coord == (12, 4) {
-            (13, 4)
-        } else {
-            coord
-        }
-    }
-
-    pub fn to_absolute_transposed(&self, rows: u8, top_row: u8) -> (u8, u8) {
-        let abs = self.to_absolute(rows, top_row);
-        (abs.1, abs.0)
-    }
-}
-
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WootingDeviceType {
     Keyboard,
     Keyboard60,
@@ -57,116 +39,445 @@ impl TryFrom<i32> for WootingDeviceType {
     }
 }
 
+/// The device whose colours are currently held in the SDK's colour buffer
+/// The buffer is shared between all devices, so switching device means the whole frame has to be rewritten
+static BUFFERED_DEVICE: AtomicU8 = AtomicU8::new(u8::MAX);
+/// Held while a frame is written, so devices can't be reset partway through one
+static WRITING: Mutex<()> = Mutex::new(());
+/// Set once devices are being reset on shutdown, after which no more frames are written
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// How long to wait for a frame being written to finish before resetting devices anyway
+const STOP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Stop every [Keyboard] from writing frames, waiting for one that's being written to finish
+/// The lock is returned so the caller can reset devices before anything else touches the SDK
+/// Waiting gives up after [STOP_TIMEOUT], as the frame may be being written by a thread that has panicked
+pub fn stop_writing() -> Option<MutexGuard<'static, ()>> {
+    STOPPED.store(true, Ordering::SeqCst);
+
+    let start = Instant::now();
+    loop {
+        match WRITING.try_lock() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(e)) => return Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) if start.elapsed() < STOP_TIMEOUT => sleep(Duration::from_millis(1)),
+            Err(TryLockError::WouldBlock) => return None,
+        }
+    }
+}
+
 // ManuallyDrop okay here because RgbKeyboard is a ZST
 pub struct Keyboard {
     rgb_keys: ManuallyDrop<RgbKeyboard>,
+    /// What the theme was built from, so it's only replaced when it changes
+    source: ThemeSource,
     theme: Box<dyn Theme>,
-    max_row: u8,
-    first_row: u8,
-    cols: u8,
+    /// How long switching themes takes
+    crossfade: Duration,
+    /// Only used while the previous theme is fading out
+    fade: Option<Crossfade>,
+    device: u8,
+    band_offset: usize,
+    layout: KeyLayout,
+    /// The number of spectrum columns the keyboard has when every key shows the spectrum
+    max_cols: u8,
+    /// Matrix cells of the keys in each spectrum column, indexed by [row from the bottom][column]
+    grid: KeyGrid,
+    zones: Vec<ZoneDisplay>,
+    orientation: Orientation,
+    /// The grid rearranged by the orientation, indexed by [distance along the bar][band]
+    bars: KeyGrid,
+    /// Colours of every key as drawn, before they're corrected for the LEDs
+    colours: HashMap<(u8, u8), Rgb>,
+    correction: Correction,
+    /// Multipliers for each channel that change over time, such as for night mode
+    tint: [f32; 3],
+    /// Only used when flashing is being limited
+    flash_limit: Option<FlashLimiter>,
+    /// Only used when dithering is enabled
+    dither: Option<TemporalDither>,
+    frame: FrameDiff,
 }
 
 impl Keyboard {
     // Management methods
-    pub fn new(rgb_keys: RgbKeyboard, theme: Box<dyn Theme>, max_row: u8, first_row: u8, cols: u8) -> Self {
-        Self { rgb_keys: ManuallyDrop::new(rgb_keys), theme, first_row, max_row, cols }
+    pub fn new(rgb_keys: RgbKeyboard, source: ThemeSource, device: u8, layout: KeyLayout, cols: u8) -> Self {
+        // Devices may have been selected while enumerating, so the SDK buffer can't be trusted
+        BUFFERED_DEVICE.store(u8::MAX, Ordering::Relaxed);
+        let grid = layout.columns(cols);
+
+        Self {
+            rgb_keys: ManuallyDrop::new(rgb_keys),
+            theme: source.get_theme(),
+            source,
+            crossfade: Duration::ZERO,
+            fade: None,
+            device,
+            band_offset: 0,
+            bars: Orientation::default().arrange(&grid, &layout, |_| true),
+            grid,
+            zones: Vec::new(),
+            layout,
+            max_cols: cols,
+            orientation: Orientation::default(),
+            colours: HashMap::new(),
+            correction: Correction::default(),
+            tint: [1.; 3],
+            flash_limit: None,
+            dither: None,
+            frame: FrameDiff::default(),
+        }
     }
 
-    pub fn from_meta_and_theme(meta: WOOTING_USB_META, theme: Box<dyn Theme>) -> Self {
-        let first_row = match WootingDeviceType::try_from(meta.device_type) {
-            Ok(WootingDeviceType::Keyboard60) => 1,
-            Ok(_) => 0,
-            Err(_) => panic!("Unknown device type"),
-        };
-        Self::new(RgbKeyboard, theme, meta.max_rows, first_row, meta.max_columns)
+    /// Construct a [Keyboard] for the device with the given index, as passed to `wooting_usb_select_device`
+    pub fn from_meta_and_theme(device: u8, meta: WOOTING_USB_META, theme: ThemeSource) -> Self {
+        Self::new(RgbKeyboard, theme, device, KeyLayout::for_device(&meta), meta.max_columns)
     }
 
+    /// Close the keyboard, returning the [RgbKeyboard] that resets it when dropped
     #[must_use = "Contained RgbKeyboard needs to be manually dropped"]
     pub fn close(self) -> ManuallyDrop<RgbKeyboard> {
+        self.select();
         self.rgb_keys
     }
 
-    pub fn set_theme(&mut self, theme: Box<dyn Theme>) {
-        self.theme = theme
+    /// Return the keyboard to its original colours without closing the SDK
+    /// Used for every keyboard but the last when multiple are being driven
+    pub fn reset(self) {
+        self.select();
+        unsafe { wooting_rgb_reset_rgb() };
+    }
+
+    /// Switch theme, fading from the previous one if there's a crossfade
+    /// Nothing happens if the theme hasn't changed, so reloading other options doesn't restart it
+    /// The previous theme is faded straight out if it was still fading in
+    pub fn set_theme(&mut self, source: ThemeSource) {
+        if source == self.source {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut self.theme, source.get_theme());
+        self.source = source;
+        self.fade = (!self.crossfade.is_zero()).then(|| Crossfade::new(previous, self.crossfade));
+    }
+
+    /// Change how long switching themes takes, where zero switches immediately
+    pub fn set_crossfade(&mut self, duration: Duration) {
+        self.crossfade = duration
+    }
+
+    /// Change how bands are laid out, which may change the number of bands this keyboard displays
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        let spectrum = |k: &PlacedKey| !self.zones.iter().any(|z| z.contains(k.matrix));
+        self.bars = orientation.arrange(&self.grid, &self.layout, spectrum);
+        // Keys that are no longer part of a bar would otherwise keep their last colour
+        for key in self.layout.keys() {
+            self.colours.insert(key.matrix, Rgb(0, 0, 0));
+        }
+    }
+
+    /// Give zones of the keyboard their own visualisation, with an optional theme overriding the keyboard's
+    /// The spectrum is spread over the zones that aren't listed or are set to [Visualisation::Spectrum], which may
+    /// change the number of bands this keyboard displays
+    /// Zones that keep their visualisation fade between themes like the keyboard does
+    pub fn set_zones(&mut self, zones: Vec<ZoneSetting>) {
+        let spectrum =
+            |k: &PlacedKey| !zones.iter().any(|(z, v, _)| *z == Zone::of(k.matrix) && *v != Visualisation::Spectrum);
+        self.grid = self.layout.columns_where(self.max_cols, spectrum);
+
+        let mut previous = std::mem::take(&mut self.zones);
+        let zones = zones
+            .into_iter()
+            .filter(|(_, visualisation, _)| *visualisation != Visualisation::Spectrum)
+            .map(|(zone, visualisation, theme)| {
+                match previous.iter().position(|display| display.shows(zone, visualisation)) {
+                    Some(i) => {
+                        let mut display = previous.swap_remove(i);
+                        display.set_theme(theme, self.crossfade, &self.source);
+                        display
+                    }
+                    None => ZoneDisplay::new(zone, visualisation, theme, &self.layout),
+                }
+            })
+            .collect();
+        self.zones = zones;
+
+        self.set_orientation(self.orientation);
+    }
+
+    /// Change the brightness and calibration that colours are corrected with before being written
+    pub fn set_correction(&mut self, correction: Correction) {
+        self.correction = correction
+    }
+
+    /// Multiply each channel of the output, after the colours have been corrected
+    pub fn set_tint(&mut self, tint: [f32; 3]) {
+        self.tint = tint
+    }
+
+    /// Enable or disable temporal dithering, which smooths out fades at low brightness
+    pub fn set_dithering(&mut self, enabled: bool) {
+        if enabled != self.dither.is_some() {
+            self.dither = enabled.then(TemporalDither::default);
+        }
+    }
+
+    /// Limit how often keys can flash, given the rate frames are displayed at, or stop limiting with [None]
+    pub fn set_flash_limit(&mut self, fps: Option<u32>) {
+        if fps.is_some() != self.flash_limit.is_some() {
+            self.flash_limit = fps.map(FlashLimiter::new);
+        }
     }
 
     // Draw methods
 
-    pub fn display(&mut self, spec_mem: &SpectrumMemory, show_caps: bool) {
-        for (col, (_freq, weight)) in spec_mem.weights.iter().enumerate() {
-            self.draw_column(col as u8, *weight)
+    /// Draw this keyboard's share of the spectrum, starting from its band offset
+    /// Overlays are blended on top in order, after the spectrum and zones have been drawn
+    pub fn display(&mut self, ctx: &FrameContext, overlays: &[Overlay]) {
+        self.theme.update(ctx);
+        self.zones.iter_mut().for_each(|z| z.update(ctx));
+        if let Some(fade) = self.fade.as_mut() {
+            fade.update(ctx);
         }
 
-        if show_caps && toggle_keys::get_caps_lock_state() {
-            self.rgb_keys.array_set_single(Key::CapsLock, 255, 255, 255);
+        let bands = ctx.spectrum.weights.iter().skip(self.band_offset).take(self.cols().into());
+        for (col, (freq, weight)) in bands.enumerate() {
+            self.draw_column(col as u8, *weight, &ctx.with_frequency(*freq))
         }
 
-        self.rgb_keys.array_update();
+        let zone_keys: Vec<_> = match self.fade.as_ref() {
+            Some(fade) => self.zones.iter().flat_map(|z| z.draw(self, &fade.over(self.theme.as_ref()), ctx)).collect(),
+            None => self.zones.iter().flat_map(|z| z.draw(self, self.theme.as_ref(), ctx)).collect(),
+        };
+        for (coord, rgb) in zone_keys {
+            self.colours.insert(coord, rgb);
+        }
+
+        if self.fade.as_ref().is_some_and(Crossfade::is_finished) {
+            self.fade = None;
+        }
+
+        self.draw_overlays(overlays);
+        self.flush();
     }
 
+    /// Blend overlays over the drawn colours, skipping keys this keyboard doesn't have, such as Num Lock on a 60%
+    fn draw_overlays(&mut self, overlays: &[Overlay]) {
+        for overlay in overlays {
+            for coord in overlay.keys.iter().copied().filter(|coord| self.layout.contains(*coord)) {
+                let below = self.colours.get(&coord).copied().unwrap_or(Rgb(0, 0, 0));
+                self.colours.insert(coord, overlay.layer(below));
+            }
+        }
+    }
+
+    /// Set the colour of every key at a point along a bar
     pub fn set_point(&mut self, row: u8, col: u8, rgb: Rgb) {
-        let coord = self.rearrange_coord(col, row);
-        self.rgb_keys.array_set_single(coord, rgb.0, rgb.1, rgb.2);
+        for coord in self.bars[usize::from(row)][usize::from(col)].iter() {
+            self.colours.insert(*coord, rgb);
+        }
     }
 
-    fn draw_column(&mut self, col: u8, db: f32) {
+    /// Limit flashing, then correct and tint the frame for the LEDs, before writing the keys that changed since the last frame
+    /// The update is skipped entirely if none did
+    fn flush(&mut self) {
+        let limited = self.flash_limit.as_mut().map(|limiter| limiter.limit(&self.colours));
+        for (coord, rgb) in limited.as_ref().unwrap_or(&self.colours).iter() {
+            let mut exact = self.correction.apply_exact(*rgb);
+            for (channel, tint) in exact.iter_mut().zip(self.tint) {
+                *channel *= tint;
+            }
+
+            let rgb = match self.dither.as_mut() {
+                Some(dither) => dither.quantise(*coord, exact),
+                None => {
+                    let [r, g, b] = exact.map(|c| c.round() as u8);
+                    Rgb(r, g, b)
+                }
+            };
+            self.frame.set(*coord, rgb);
+        }
+
+        let mut changed = self.frame.flush();
+        if changed.is_empty() {
+            return;
+        }
+
+        let _writing = WRITING.lock().unwrap_or_else(|e| e.into_inner());
+        if STOPPED.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if BUFFERED_DEVICE.load(Ordering::Relaxed) != self.device {
+            self.select();
+            changed = self.frame.current();
+        }
+
+        for (coord, rgb) in changed {
+            self.rgb_keys.array_set_single(coord, rgb.0, rgb.1, rgb.2);
+        }
+        self.rgb_keys.array_update();
+    }
+
+    fn select(&self) {
+        unsafe { wooting_usb_select_device(self.device) };
+        BUFFERED_DEVICE.store(self.device, Ordering::Relaxed);
+    }
+
+    fn draw_column(&mut self, col: u8, db: f32, ctx: &FrameContext) {
         let height = db - MIN_DB;
         let height_keys = height / self.get_db_step();
 
         for row in 0..self.rows() {
-            let rgb = self.get_color(col, row, height_keys);
+            let height_keys = match self.dither.as_mut() {
+                Some(dither) => dither.bar_height((col, row), row, height_keys),
+                None => height_keys,
+            };
+            let rgb = self.get_color(col, row, height_keys, ctx);
 
             self.set_point(row, col, rgb)
         }
     }
 
-    fn get_color(&self, col: u8, row: u8, bar_height: f32) -> Rgb {
-        self.theme.get_led_color(self, col, row, bar_height)
+    fn get_color(&self, col: u8, row: u8, bar_height: f32, ctx: &FrameContext) -> Rgb {
+        match self.fade.as_ref() {
+            Some(fade) => fade.over(self.theme.as_ref()).get_frame_color(self, col, row, bar_height, ctx),
+            None => self.theme.get_frame_color(self, col, row, bar_height, ctx),
+        }
+    }
+
+    /// Set the index of the first spectrum band shown on this keyboard, for spanning a spectrum over several keyboards
+    pub fn set_band_offset(&mut self, offset: usize) {
+        self.band_offset = offset
     }
 
     // Attribute methods
+    // Rows and columns are measured along and across the bars, so themes don't need to know about the orientation
+
+    pub fn device(&self) -> u8 {
+        self.device
+    }
+
+    pub fn is_flash_limited(&self) -> bool {
+        self.flash_limit.is_some()
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.frame.stats()
+    }
+
+    /// The number of bands, each of which is drawn as a bar
     pub fn cols(&self) -> u8 {
-        self.cols
+        self.bars.first().map_or(0, Vec::len) as u8
     }
 
+    /// The length of each bar in keys
+    /// This is at least one, so zones still have a height to scale to when they cover the whole keyboard
     pub fn rows(&self) -> u8 {
-        self.max_row - self.first_row
+        self.bars.len().max(1) as u8
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    pub fn layout(&self) -> &KeyLayout {
+        &self.layout
     }
 
     pub fn get_db_step(&self) -> f32 {
         (MAX_DB - MIN_DB) / self.rows() as f32
     }
 
-    pub fn rearrange_coord(&self, col: u8, row: u8) -> (u8, u8) {
-        KeyboardCoord(col, row).to_absolute_transposed(self.rows(), self.first_row)
+    /// Gets the matrix cells of the keys at a point along a bar, where (0,0) is the base of the first band
+    pub fn rearrange_coord(&self, col: u8, row: u8) -> &[(u8, u8)] {
+        &self.bars[usize::from(row)][usize::from(col)]
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use strum::IntoEnumIterator;
+    use themes::ThemeChoice;
+
+    fn keyboard() -> Keyboard {
+        Keyboard::new(RgbKeyboard, ThemeChoice::Classic.into(), 0, KeyLayout::grid(6, 21), 21)
+    }
 
     #[test]
-    fn abs_coord_0() {
-        let c = KeyboardCoord(1, 3);
-        assert_eq!(c.to_absolute(6, 0), (1, 2))
+    fn all_zones_off() {
+        // Horizontal bars run along the spectrum columns, so there are none left when every zone is taken
+        let mut kbd = keyboard();
+        kbd.set_orientation(Orientation::Horizontal);
+        kbd.set_zones(Zone::iter().map(|zone| (zone, Visualisation::Loudness, None)).collect());
+        assert_eq!(kbd.cols(), 0);
+        assert_eq!(kbd.rows(), 1);
+        assert!(kbd.get_db_step().is_finite());
+
+        let spectrum = SpectrumMemory::default();
+        let ctx = FrameContext::new(&spectrum, Duration::ZERO, 0);
+        let keys: usize = kbd.zones.iter().map(|zone| zone.draw(&kbd, kbd.theme.as_ref(), &ctx).len()).sum();
+        assert_eq!(keys, 6 * 21)
     }
 
     #[test]
-    fn abs_coord_1() {
-        let c = KeyboardCoord(1, 3);
-        assert_eq!(c.to_absolute(5, 1), (1, 2))
+    fn dithered_fade() {
+        // A single row, so Classic draws it in full red
+        let mut kbd = Keyboard::new(RgbKeyboard, ThemeChoice::Classic.into(), 0, KeyLayout::grid(1, 21), 21);
+        kbd.set_dithering(true);
+        let spectrum = SpectrumMemory::default();
+        let ctx = FrameContext::new(&spectrum, Duration::ZERO, 0);
+
+        // Fading by less than one 8-bit step over the frames, at full brightness
+        let reds: Vec<u8> = (0..8)
+            .map(|frame| {
+                let height = 0.4 + frame as f32 * 0.00025;
+                kbd.draw_column(0, MIN_DB + height * (MAX_DB - MIN_DB), &ctx);
+                kbd.colours[&(0, 0)].0
+            })
+            .collect();
+
+        assert!(reds.iter().all(|red| (102..=103).contains(red)), "{reds:?}");
+        assert!(reds.windows(2).any(|w| w[0] != w[1]), "{reds:?}")
     }
 
     #[test]
-    fn abs_coord_2() {
-        let c = KeyboardCoord(0, 0);
-        assert_eq!(c.to_absolute(5, 1), (0, 5))
+    fn unchanged_theme_kept() {
+        let mut kbd = keyboard();
+        kbd.set_crossfade(Duration::from_millis(500));
+
+        kbd.set_theme(ThemeChoice::Classic.into());
+        assert!(kbd.fade.is_none());
+        kbd.set_theme(ThemeChoice::Fire.into());
+        assert!(kbd.fade.is_some())
     }
 
     #[test]
-    fn abs_coord_t() {
-        let c = KeyboardCoord(1, 2);
-        assert_eq!(c.to_absolute_transposed(5, 1), (3, 1))
+    fn zones_fade() {
+        let mut kbd = keyboard();
+        kbd.set_crossfade(Duration::from_millis(500));
+        let zones = |theme: Option<ThemeChoice>| vec![(Zone::Numpad, Visualisation::Pulse, theme.map(Into::into))];
+
+        kbd.set_zones(zones(Some(ThemeChoice::Grape)));
+        assert!(!kbd.zones[0].is_fading());
+        kbd.set_zones(zones(Some(ThemeChoice::Grape)));
+        assert!(!kbd.zones[0].is_fading());
+
+        kbd.set_zones(zones(Some(ThemeChoice::Fire)));
+        assert!(kbd.zones[0].is_fading());
+        kbd.set_zones(zones(None));
+        assert!(kbd.zones[0].is_fading())
+    }
+
+    #[test]
+    fn missing_overlay_keys() {
+        let mut kbd = Keyboard::new(RgbKeyboard, ThemeChoice::Classic.into(), 0, KeyLayout::grid(6, 14), 14);
+        let overlay = |indicator: Indicator| Overlay { keys: vec![indicator.matrix()], ..Default::default() };
+        kbd.draw_overlays(&[overlay(Indicator::NumLock), overlay(Indicator::CapsLock)]);
+
+        assert!(!kbd.colours.contains_key(&Indicator::NumLock.matrix()));
+        assert_eq!(kbd.colours.get(&Indicator::CapsLock.matrix()), Some(&Rgb(255, 255, 255)))
     }
 }
@@ -0,0 +1,63 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// A single-slot handoff for passing frames between a producer and a consumer thread
+/// Publishing overwrites any frame that hasn't been taken yet, so the consumer only ever receives the newest one
+#[derive(Debug, Default)]
+pub struct LatestFrame<T> {
+    slot: Mutex<Option<T>>,
+    ready: Condvar,
+}
+
+impl<T> LatestFrame<T> {
+    /// Construct a new, empty [LatestFrame]
+    pub fn new() -> Self {
+        Self { slot: Mutex::new(None), ready: Condvar::new() }
+    }
+
+    /// Store a frame, replacing any frame that is still waiting to be taken
+    pub fn publish(&self, frame: T) {
+        *self.slot.lock().unwrap() = Some(frame);
+        self.ready.notify_one();
+    }
+
+    /// Take the newest frame if one has been published since the last take
+    pub fn take(&self) -> Option<T> {
+        self.slot.lock().unwrap().take()
+    }
+
+    /// Take the newest frame, waiting up to the given duration for one to be published
+    pub fn take_timeout(&self, timeout: Duration) -> Option<T> {
+        let slot = self.slot.lock().unwrap();
+        let (mut slot, _) = self.ready.wait_timeout_while(slot, timeout, |s| s.is_none()).unwrap();
+        slot.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_empty() {
+        let frame: LatestFrame<u8> = LatestFrame::new();
+        assert_eq!(frame.take(), None)
+    }
+
+    #[test]
+    fn newest_wins() {
+        let frame = LatestFrame::new();
+        frame.publish(1);
+        frame.publish(2);
+        assert_eq!(frame.take(), Some(2));
+        assert_eq!(frame.take(), None)
+    }
+
+    #[test]
+    fn take_timeout_across_threads() {
+        let frame = std::sync::Arc::new(LatestFrame::new());
+        let producer = frame.clone();
+        std::thread::spawn(move || producer.publish(3));
+        assert_eq!(frame.take_timeout(Duration::from_secs(5)), Some(3))
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{KeyGrid, WootingDeviceType};
+
+use std::ffi::CStr;
+
+use wooting_rgb_sys::{WOOTING_DEVICE_LAYOUT_LAYOUT_ISO, WOOTING_USB_META};
+
+/// A cell within a row of a [LayoutDef], read from left to right
+/// Widths are measured in key units, where 1 is the width of a letter key
+#[derive(Debug)]
+pub enum Cell {
+    /// A physical key wired to the given matrix column
+    Key(u8, f32),
+    /// Empty space between keys
+    Gap(f32),
+    /// A cell that only exists on ANSI boards
+    Ansi(&'static Cell),
+    /// A cell that only exists on ISO boards
+    Iso(&'static Cell),
+}
+
+use Cell::*;
+
+/// Shorthand for a regular 1u key
+const fn u(col: u8) -> Cell {
+    Key(col, 1.)
+}
+
+/// Describes the physical layout of a keyboard model
+/// Adding support for a new model only requires adding a new definition to [LAYOUTS]
+#[derive(Debug)]
+pub struct LayoutDef {
+    /// Model names this layout applies to, matched against the start of the name reported by the SDK
+    pub models: &'static [&'static str],
+    /// Device type to fall back on if no model name matches
+    pub device_type: Option<WootingDeviceType>,
+    /// Each row of keys from top to bottom, along with the matrix row it's wired to
+    pub rows: &'static [(u8, &'static [Cell])],
+}
+
+impl LayoutDef {
+    /// Find the definition for a model, falling back on one for the same type of device
+    pub fn find(model: &str, device_type: Option<WootingDeviceType>) -> Option<&'static Self> {
+        LAYOUTS
+            .iter()
+            .find(|def| def.models.iter().any(|m| model.starts_with(m)))
+            .or_else(|| LAYOUTS.iter().find(|def| device_type.is_some() && def.device_type == device_type))
+    }
+}
+
+#[rustfmt::skip]
+const FULL_SIZE: LayoutDef = LayoutDef {
+    models: &["Wooting Two"],
+    device_type: Some(WootingDeviceType::Keyboard),
+    rows: &[
+        (0, &[u(0), Gap(1.), u(2), u(3), u(4), u(5), Gap(0.5), u(6), u(7), u(8), u(9), Gap(0.5), u(10), u(11), u(12), u(13),
+            Gap(0.25), u(14), u(15), u(16), Gap(0.25), u(17), u(18), u(19), u(20)]),
+        (1, &[u(0), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Key(13, 2.),
+            Gap(0.25), u(14), u(15), u(16), Gap(0.25), u(17), u(18), u(19), u(20)]),
+        (2, &[Key(0, 1.5), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Ansi(&Key(13, 1.5)),
+            Iso(&Gap(1.5)), Gap(0.25), u(14), u(15), u(16), Gap(0.25), u(17), u(18), u(19), u(20)]),
+        (3, &[Key(0, 1.75), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), Ansi(&Key(13, 2.25)),
+            Iso(&u(12)), Iso(&Key(13, 1.25)), Gap(3.5), u(17), u(18), u(19)]),
+        (4, &[Ansi(&Key(0, 2.25)), Iso(&Key(0, 1.25)), Iso(&u(1)), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10),
+            u(11), Key(13, 2.75), Gap(1.25), u(15), Gap(1.25), u(17), u(18), u(19), u(20)]),
+        (5, &[Key(0, 1.25), Key(1, 1.25), Key(2, 1.25), Key(6, 6.25), Key(10, 1.25), Key(11, 1.25), Key(12, 1.25),
+            Key(13, 1.25), Gap(0.25), u(14), u(15), u(16), Gap(0.25), Key(18, 2.), u(19)]),
+    ],
+};
+
+#[rustfmt::skip]
+const TKL: LayoutDef = LayoutDef {
+    models: &["Wooting One"],
+    device_type: Some(WootingDeviceType::KeyboardTKL),
+    rows: &[
+        (0, &[u(0), Gap(1.), u(2), u(3), u(4), u(5), Gap(0.5), u(6), u(7), u(8), u(9), Gap(0.5), u(10), u(11), u(12), u(13),
+            Gap(0.25), u(14), u(15), u(16)]),
+        (1, &[u(0), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Key(13, 2.),
+            Gap(0.25), u(14), u(15), u(16)]),
+        (2, &[Key(0, 1.5), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Ansi(&Key(13, 1.5)),
+            Iso(&Gap(1.5)), Gap(0.25), u(14), u(15), u(16)]),
+        (3, &[Key(0, 1.75), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), Ansi(&Key(13, 2.25)),
+            Iso(&u(12)), Iso(&Key(13, 1.25))]),
+        (4, &[Ansi(&Key(0, 2.25)), Iso(&Key(0, 1.25)), Iso(&u(1)), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10),
+            u(11), Key(13, 2.75), Gap(1.25), u(15)]),
+        (5, &[Key(0, 1.25), Key(1, 1.25), Key(2, 1.25), Key(6, 6.25), Key(10, 1.25), Key(11, 1.25), Key(12, 1.25),
+            Key(13, 1.25), Gap(0.25), u(14), u(15), u(16)]),
+    ],
+};
+
+/// The 80HE is wired like the One, so it shares its rows until its own positions have been checked on hardware
+const EIGHTY_PERCENT: LayoutDef = LayoutDef { models: &["Wooting 80HE"], device_type: None, rows: TKL.rows };
+
+/// The 60HE has no function row, so its keys start on the second matrix row
+#[rustfmt::skip]
+const SIXTY_PERCENT: LayoutDef = LayoutDef {
+    models: &["Wooting 60HE"],
+    device_type: Some(WootingDeviceType::Keyboard60),
+    rows: &[
+        (1, &[u(0), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Key(13, 2.)]),
+        (2, &[Key(0, 1.5), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), u(12), Ansi(&Key(13, 1.5)),
+            Iso(&Gap(1.5))]),
+        (3, &[Key(0, 1.75), u(1), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10), u(11), Ansi(&Key(13, 2.25)),
+            Iso(&u(12)), Iso(&Key(13, 1.25))]),
+        (4, &[Ansi(&Key(0, 2.25)), Iso(&Key(0, 1.25)), Iso(&u(1)), u(2), u(3), u(4), u(5), u(6), u(7), u(8), u(9), u(10),
+            u(11), Key(13, 2.75)]),
+        (5, &[Key(0, 1.25), Key(1, 1.25), Key(2, 1.25), Key(6, 6.25), Key(10, 1.25), Key(11, 1.25), Key(12, 1.25),
+            Key(13, 1.25)]),
+    ],
+};
+
+/// The UwU's three keys sit in a single row
+const THREE_KEY: LayoutDef = LayoutDef {
+    models: &["Wooting UwU"],
+    device_type: Some(WootingDeviceType::KeypadThreeKey),
+    rows: &[(0, &[u(0), u(1), u(2)])],
+};
+
+/// Every known keyboard layout, checked in order
+pub const LAYOUTS: &[LayoutDef] = &[FULL_SIZE, TKL, EIGHTY_PERCENT, SIXTY_PERCENT, THREE_KEY];
+
+/// Whether the board has ANSI or ISO keycaps, which changes the shape of some rows
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Physical {
+    #[default]
+    Ansi,
+    Iso,
+}
+
+/// A key with its position resolved from a [LayoutDef]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacedKey {
+    /// The (row, col) matrix cell that the key is wired to
+    pub matrix: (u8, u8),
+    /// Distance from the left edge of the board to the left edge of the key
+    pub x: f32,
+    pub width: f32,
+}
+
+impl PlacedKey {
+    pub fn centre(&self) -> f32 {
+        self.x + self.width / 2.
+    }
+}
+
+/// The physical keys of a specific keyboard, arranged in rows from top to bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLayout {
+    rows: Vec<Vec<PlacedKey>>,
+    width: f32,
+}
+
+impl KeyLayout {
+    /// Resolve the positions of every key in a definition
+    pub fn from_def(def: &LayoutDef, physical: Physical) -> Self {
+        let rows: Vec<Vec<PlacedKey>> = def
+            .rows
+            .iter()
+            .map(|(matrix_row, cells)| {
+                let mut x = 0.;
+                let mut keys = Vec::new();
+                for cell in cells.iter() {
+                    Self::place(cell, physical, *matrix_row, &mut x, &mut keys);
+                }
+                keys
+            })
+            .collect();
+
+        let width = rows.iter().flatten().map(|k| k.x + k.width).fold(0., f32::max);
+
+        Self { rows, width }
+    }
+
+    fn place(cell: &Cell, physical: Physical, matrix_row: u8, x: &mut f32, keys: &mut Vec<PlacedKey>) {
+        match (cell, physical) {
+            (Key(col, width), _) => {
+                keys.push(PlacedKey { matrix: (matrix_row, *col), x: *x, width: *width });
+                *x += width;
+            }
+            (Gap(width), _) => *x += width,
+            (Ansi(cell), Physical::Ansi) | (Iso(cell), Physical::Iso) => {
+                Self::place(cell, physical, matrix_row, x, keys)
+            }
+            (Ansi(_), Physical::Iso) | (Iso(_), Physical::Ansi) => (),
+        }
+    }
+
+    /// A layout where every matrix cell is a 1u key, used for devices without a definition
+    pub fn grid(rows: u8, cols: u8) -> Self {
+        let rows = (0..rows)
+            .map(|row| (0..cols).map(|col| PlacedKey { matrix: (row, col), x: col.into(), width: 1. }).collect())
+            .collect();
+
+        Self { rows, width: cols.into() }
+    }
+
+    /// Find the layout for a connected device, falling back on a plain grid for unknown models
+    pub fn for_device(meta: &WOOTING_USB_META) -> Self {
+        let model = model_name(meta);
+        let device_type = WootingDeviceType::try_from(meta.device_type).ok();
+        let physical = if meta.layout == WOOTING_DEVICE_LAYOUT_LAYOUT_ISO { Physical::Iso } else { Physical::Ansi };
+
+        LayoutDef::find(&model, device_type)
+            .map(|def| Self::from_def(def, physical))
+            .unwrap_or_else(|| Self::grid(meta.max_rows, meta.max_columns))
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows.len() as u8
+    }
+
+    /// Width of the widest row in key units
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    /// Whether there's a key wired to a matrix cell
+    pub fn contains(&self, matrix: (u8, u8)) -> bool {
+        self.keys().any(|k| k.matrix == matrix)
+    }
+
+    /// All keys, from the top left to the bottom right
+    pub fn keys(&self) -> impl Iterator<Item = &PlacedKey> {
+        self.rows.iter().flatten()
+    }
+
+    /// All keys, along with their row counted from the bottom
+    pub fn keys_with_rows(&self) -> impl Iterator<Item = (u8, &PlacedKey)> {
+        self.rows.iter().rev().enumerate().flat_map(|(row, keys)| keys.iter().map(move |k| (row as u8, k)))
+    }
+
+    /// Split the board into a number of evenly spaced columns, returning the matrix cells of the keys within each
+    /// A key belongs to whichever column its centre falls in, so wide keys aren't drawn more than once
+    /// The result is indexed by [row from the bottom][column]
+    pub fn columns(&self, cols: u8) -> KeyGrid {
+        self.columns_where(cols, |_| true)
+    }
+
+    /// Does the same as [Self::columns], but only includes keys that match a filter
+    /// The columns only span the included keys, so there are proportionally fewer of them when only part of the board
+    /// is included
+    pub fn columns_where(&self, cols: u8, filter: impl Fn(&PlacedKey) -> bool) -> KeyGrid {
+        let included = || self.keys().filter(|k| filter(k));
+        let start = included().map(|k| k.x).fold(f32::INFINITY, f32::min);
+        let end = included().map(|k| k.x + k.width).fold(0., f32::max);
+        let width = (end - start).max(0.);
+        let cols = (cols as f32 * width / self.width).round() as usize;
+
+        self.rows
+            .iter()
+            .rev()
+            .map(|row| {
+                let mut columns = vec![Vec::new(); cols];
+                for key in row.iter().filter(|k| filter(k)) {
+                    let col = ((key.centre() - start) / width * cols as f32) as usize;
+                    if let Some(column) = columns.get_mut(col) {
+                        column.push(key.matrix)
+                    }
+                }
+                columns
+            })
+            .collect()
+    }
+}
+
+/// Gets the model name reported by the SDK, such as "Wooting Two HE"
+pub fn model_name(meta: &WOOTING_USB_META) -> String {
+    if meta.model.is_null() {
+        String::from("Unknown Keyboard")
+    } else {
+        unsafe { CStr::from_ptr(meta.model) }.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_aligned() {
+        // Keys to the right of the main block are wired to the same column as the keys above and below them
+        for def in LAYOUTS {
+            for physical in [Physical::Ansi, Physical::Iso] {
+                let layout = KeyLayout::from_def(def, physical);
+                for a in layout.keys().filter(|k| k.matrix.1 >= 14 && k.width == 1.) {
+                    for b in layout.keys().filter(|k| k.matrix.1 == a.matrix.1 && k.width == 1.) {
+                        assert_eq!(a.x, b.x, "{:?} {physical:?}: {a:?} {b:?}", def.models)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unique_matrix_cells() {
+        for def in LAYOUTS {
+            let layout = KeyLayout::from_def(def, Physical::Iso);
+            let mut cells: Vec<(u8, u8)> = layout.keys().map(|k| k.matrix).collect();
+            let len = cells.len();
+            cells.sort();
+            cells.dedup();
+            assert_eq!(cells.len(), len, "{:?}", def.models)
+        }
+    }
+
+    #[test]
+    fn find_def() {
+        let models = |model, device_type| LayoutDef::find(model, device_type).map(|def| def.models);
+        assert_eq!(models("Wooting 80HE", Some(WootingDeviceType::KeyboardTKL)), Some(EIGHTY_PERCENT.models));
+        assert_eq!(models("Wooting One", Some(WootingDeviceType::KeyboardTKL)), Some(TKL.models));
+        assert_eq!(models("Wooting Two HE (ARM)", None), Some(FULL_SIZE.models));
+        assert_eq!(models("Wooting UwU RGB", Some(WootingDeviceType::KeypadThreeKey)), Some(THREE_KEY.models))
+    }
+
+    #[test]
+    fn bottom_left_ctrl() {
+        let layout = KeyLayout::from_def(&FULL_SIZE, Physical::Ansi);
+        assert_eq!(layout.columns(21)[0][0], vec![(5, 0)])
+    }
+
+    #[test]
+    fn shift_under_column() {
+        // Right shift is wired to column 13, but sits over column 12
+        let layout = KeyLayout::from_def(&FULL_SIZE, Physical::Ansi);
+        assert_eq!(layout.columns(21)[1][12], vec![(4, 13)])
+    }
+
+    #[test]
+    fn sixty_percent_rows() {
+        let layout = KeyLayout::from_def(&SIXTY_PERCENT, Physical::Ansi);
+        assert_eq!(layout.rows(), 5);
+        assert_eq!(layout.columns(14)[4][0], vec![(1, 0)])
+    }
+
+    #[test]
+    fn iso_keys() {
+        let ansi = KeyLayout::from_def(&FULL_SIZE, Physical::Ansi);
+        let iso = KeyLayout::from_def(&FULL_SIZE, Physical::Iso);
+        assert!(!ansi.keys().any(|k| k.matrix == (4, 1)));
+        assert!(iso.keys().any(|k| k.matrix == (4, 1)))
+    }
+
+    #[test]
+    fn partial_columns() {
+        // The main block is two thirds of the width of a full size board
+        let layout = KeyLayout::from_def(&FULL_SIZE, Physical::Ansi);
+        let columns = layout.columns_where(21, |k| k.matrix.1 < 14);
+        assert_eq!(columns[0].len(), 14);
+        assert_eq!(columns[0][0], vec![(5, 0)])
+    }
+
+    #[test]
+    fn grid() {
+        let layout = KeyLayout::grid(2, 3);
+        let columns = layout.columns(3);
+        assert_eq!(columns[0], vec![vec![(1, 0)], vec![(1, 1)], vec![(1, 2)]]);
+        assert_eq!(columns[1], vec![vec![(0, 0)], vec![(0, 1)], vec![(0, 2)]])
+    }
+}
@@ -1,15 +1,47 @@
 mod cols;
+mod correction;
+mod dither;
+mod error;
+mod flash_limit;
+mod frame_diff;
 mod freq_weight;
+mod gradient;
 mod keyboard;
+mod latest_frame;
+mod layout;
+mod night;
+mod orientation;
+mod overlay;
+mod palette;
+mod script;
 mod spectrum_memory;
+#[cfg(test)]
+mod temp_dir;
 pub mod themes;
 mod traits;
+mod zones;
 
 pub use cols::*;
+pub use correction::*;
+pub use dither::*;
+pub use error::*;
+pub use flash_limit::*;
+pub use frame_diff::*;
 pub use freq_weight::*;
+pub use gradient::*;
 pub use keyboard::*;
+pub use latest_frame::*;
+pub use layout::*;
+pub use night::*;
+pub use orientation::*;
+pub use overlay::*;
+pub use palette::*;
+pub use script::*;
 pub use spectrum_memory::*;
+#[cfg(test)]
+pub use temp_dir::*;
 pub use traits::*;
+pub use zones::*;
 
 // pub type Rgb = palette::rgb::Rgb<palette::Srgb, u8>;
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
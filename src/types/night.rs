@@ -0,0 +1,158 @@
+use chrono::{Local, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: f32 = 24. * 60.;
+
+/// A source of the local time of day, so schedules can be tested without waiting for them
+pub trait Clock {
+    fn time_of_day(&self) -> NaiveTime;
+}
+
+/// The computer's local time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn time_of_day(&self) -> NaiveTime {
+        Local::now().time()
+    }
+}
+
+/// Warms and dims the lighting overnight
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NightMode {
+    pub enabled: bool,
+    /// When night mode starts fading in
+    pub start: NaiveTime,
+    /// When night mode has fully faded out
+    pub end: NaiveTime,
+    /// Colour temperature at night in Kelvin, where lower is warmer and 6500 is neutral
+    pub temperature: u32,
+    /// Brightness multiplier at night, from 0 to 1
+    pub brightness: f32,
+    /// How long fading in and out takes, in minutes
+    pub transition: u32,
+}
+
+impl Default for NightMode {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            temperature: 3400,
+            brightness: 0.5,
+            transition: 30,
+        }
+    }
+}
+
+impl NightMode {
+    /// Multipliers for the red, green and blue channels at the clock's current time
+    pub fn tint(&self, clock: &impl Clock) -> [f32; 3] {
+        let strength = if self.enabled { self.strength(clock.time_of_day()) } else { 0. };
+        let night = temperature_to_rgb(self.temperature).map(|c| c * self.brightness.clamp(0., 1.));
+        night.map(|c| 1. + (c - 1.) * strength)
+    }
+
+    /// How far into night mode a time is, from 0 during the day to 1 at night, fading between them
+    fn strength(&self, time: NaiveTime) -> f32 {
+        let (start, end, time) = (minutes(self.start), minutes(self.end), minutes(time));
+        // The schedule may wrap around midnight
+        let length = (end - start).rem_euclid(MINUTES_PER_DAY);
+        let elapsed = (time - start).rem_euclid(MINUTES_PER_DAY);
+
+        if elapsed >= length {
+            return 0.;
+        }
+
+        let transition = (self.transition as f32).min(length / 2.);
+        if transition == 0. {
+            1.
+        } else {
+            (elapsed / transition).min((length - elapsed) / transition).min(1.)
+        }
+    }
+}
+
+fn minutes(time: NaiveTime) -> f32 {
+    time.num_seconds_from_midnight() as f32 / 60.
+}
+
+/// Approximate the colour of a black body at a temperature in Kelvin, as channel multipliers from 0 to 1
+/// Based on Tanner Helland's fit of the CIE colour matching functions, which is neutral at around 6600K
+fn temperature_to_rgb(kelvin: u32) -> [f32; 3] {
+    let t = kelvin.clamp(1000, 40000) as f32 / 100.;
+
+    let (r, g) = if t <= 66. {
+        (255., 99.470_8 * t.ln() - 161.119_57)
+    } else {
+        (329.698_73 * (t - 60.).powf(-0.133_204_76), 288.122_16 * (t - 60.).powf(-0.075_514_85))
+    };
+    let b = if t >= 66. {
+        255.
+    } else if t <= 19. {
+        0.
+    } else {
+        138.517_73 * (t - 10.).ln() - 305.044_8
+    };
+
+    [r, g, b].map(|c: f32| c.clamp(0., 255.) / 255.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedClock(u32, u32);
+
+    impl Clock for FixedClock {
+        fn time_of_day(&self) -> NaiveTime {
+            NaiveTime::from_hms_opt(self.0, self.1, 0).unwrap()
+        }
+    }
+
+    fn enabled() -> NightMode {
+        NightMode { enabled: true, ..Default::default() }
+    }
+
+    #[test]
+    fn day_untinted() {
+        assert_eq!(enabled().tint(&FixedClock(12, 0)), [1.; 3]);
+        assert_eq!(NightMode::default().tint(&FixedClock(23, 0)), [1.; 3])
+    }
+
+    #[test]
+    fn night_warm_and_dim() {
+        let [r, g, b] = enabled().tint(&FixedClock(2, 0));
+        assert_eq!(r, 0.5);
+        assert!(b < g && g < r)
+    }
+
+    #[test]
+    fn transitions() {
+        let night = enabled();
+        // Halfway through fading in and out, either side of midnight
+        let fade_in = night.tint(&FixedClock(21, 15));
+        let fade_out = night.tint(&FixedClock(6, 45));
+        assert_eq!(fade_in[0], 0.75);
+        assert_eq!(fade_in, fade_out)
+    }
+
+    #[test]
+    fn same_day_schedule() {
+        let night = NightMode {
+            start: NaiveTime::from_hms_opt(1, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            transition: 0,
+            ..enabled()
+        };
+        assert_eq!(night.strength(NaiveTime::from_hms_opt(0, 59, 0).unwrap()), 0.);
+        assert_eq!(night.strength(NaiveTime::from_hms_opt(1, 0, 0).unwrap()), 1.);
+        assert_eq!(night.strength(NaiveTime::from_hms_opt(5, 0, 0).unwrap()), 0.)
+    }
+
+    #[test]
+    fn neutral_temperature() {
+        assert!(temperature_to_rgb(6600).iter().all(|c| *c > 0.99))
+    }
+}
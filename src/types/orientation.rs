@@ -0,0 +1,270 @@
+use crate::{KeyLayout, PlacedKey};
+
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, EnumString};
+
+/// Matrix cells of keys arranged in a grid, indexed by [row][column]
+pub type KeyGrid = Vec<Vec<Vec<(u8, u8)>>>;
+
+/// How spectrum bands are laid out over the keyboard
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, PartialEq, Display, EnumString, AsRefStr, EnumIter)]
+pub enum Orientation {
+    /// Bass on the left, with bars growing up from the bottom row
+    #[default]
+    Standard,
+    /// Bass on the right, with bars growing up from the bottom row
+    Reversed,
+    /// Bass in the centre and treble at both edges
+    Mirrored,
+    /// Bass on the left, with bars growing down from the top row
+    Hanging,
+    /// Each row is a band, with bass at the bottom and bars growing to the right
+    Horizontal,
+    /// Every key is its own band, shown by its brightness
+    /// Bands snake back and forth along the rows, starting with bass at the bottom left
+    Snake,
+    /// Every key is its own band, shown by its brightness
+    /// Bands follow a space filling curve, so neighbouring frequencies stay close together in both directions
+    Hilbert,
+}
+
+impl Orientation {
+    /// Rearrange a grid of keys indexed by [row from the bottom][column] into bars
+    /// Modes where every key is a band skip the grid, and use each included key of the layout instead
+    /// The result is indexed by [distance along the bar][band]
+    pub fn arrange(&self, grid: &KeyGrid, layout: &KeyLayout, include: impl Fn(&PlacedKey) -> bool) -> KeyGrid {
+        let rows = grid.len();
+        let cols = grid.first().map_or(0, Vec::len);
+
+        // Gets the (col, row) cells of the grid that make up a point along a bar
+        type Targets = Box<dyn Fn(usize, usize) -> Vec<(usize, usize)>>;
+        let (bands, length, targets): (usize, usize, Targets) = match self {
+            Self::Standard => (cols, rows, Box::new(|band, step| vec![(band, step)])),
+            Self::Reversed => (cols, rows, Box::new(move |band, step| vec![(cols - 1 - band, step)])),
+            Self::Mirrored => (
+                cols.div_ceil(2),
+                rows,
+                Box::new(move |band, step| vec![((cols - 1) / 2 - band, step), (cols / 2 + band, step)]),
+            ),
+            Self::Hanging => (cols, rows, Box::new(move |band, step| vec![(band, rows - 1 - step)])),
+            Self::Horizontal => (rows, cols, Box::new(|band, step| vec![(step, band)])),
+            Self::Snake => return vec![per_key(layout, include, snake)],
+            Self::Hilbert => return vec![per_key(layout, include, gilbert)],
+        };
+
+        (0..length)
+            .map(|step| {
+                (0..bands)
+                    .map(|band| {
+                        let mut targets = targets(band, step);
+                        // The centre column of an odd width board is shared by both halves when mirrored
+                        targets.dedup();
+
+                        targets.into_iter().flat_map(|(col, row)| grid[row][col].iter().copied()).collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Turn each included key of the layout into a band with a length of one key
+/// Keys are visited in the order a path takes through a grid of 1u cells covering the board, given its (cols, rows)
+fn per_key(
+    layout: &KeyLayout,
+    include: impl Fn(&PlacedKey) -> bool,
+    path: fn(usize, usize) -> Vec<(usize, usize)>,
+) -> Vec<Vec<(u8, u8)>> {
+    let cols = (layout.width().ceil() as usize).max(1);
+    let rows = usize::from(layout.rows());
+
+    let mut order = vec![vec![0; cols]; rows];
+    for (i, (col, row)) in path(cols, rows).into_iter().enumerate() {
+        order[row][col] = i;
+    }
+
+    let mut keys: Vec<_> = layout.keys_with_rows().filter(|(_, k)| include(k)).collect();
+    // Keys are sorted by their position too, in case two share a cell
+    keys.sort_by(|(a_row, a), (b_row, b)| {
+        let cell = |row: u8, k: &PlacedKey| order[usize::from(row)][(k.centre() as usize).min(cols - 1)];
+        cell(*a_row, a).cmp(&cell(*b_row, b)).then(a.x.total_cmp(&b.x))
+    });
+    keys.into_iter().map(|(_, k)| vec![k.matrix]).collect()
+}
+
+/// Visit every (col, row) of a grid, alternating direction on each row
+fn snake(cols: usize, rows: usize) -> Vec<(usize, usize)> {
+    (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| if row % 2 == 0 { (col, row) } else { (cols - 1 - col, row) }))
+        .collect()
+}
+
+/// Visit every (col, row) of a grid along a generalised Hilbert curve, which works for any size of rectangle
+/// Based on Jakub Červený's gilbert2d algorithm
+fn gilbert(cols: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut out = Vec::with_capacity(cols * rows);
+    let (w, h) = (cols as i32, rows as i32);
+
+    if w >= h {
+        gilbert_step(&mut out, (0, 0), (w, 0), (0, h));
+    } else {
+        gilbert_step(&mut out, (0, 0), (0, h), (w, 0));
+    }
+
+    out.into_iter().map(|(x, y)| (x as usize, y as usize)).collect()
+}
+
+/// Fill the rectangle starting at `pos`, spanned by the major axis `a` and the minor axis `b`
+fn gilbert_step(out: &mut Vec<(i32, i32)>, pos: (i32, i32), a: (i32, i32), b: (i32, i32)) {
+    let (x, y) = pos;
+    let (w, h) = ((a.0 + a.1).abs(), (b.0 + b.1).abs());
+    // Unit vectors along each axis
+    let da = (a.0.signum(), a.1.signum());
+    let db = (b.0.signum(), b.1.signum());
+
+    if w == 0 || h == 0 {
+        return;
+    }
+
+    if h == 1 {
+        out.extend((0..w).map(|i| (x + da.0 * i, y + da.1 * i)));
+        return;
+    }
+
+    if w == 1 {
+        out.extend((0..h).map(|i| (x + db.0 * i, y + db.1 * i)));
+        return;
+    }
+
+    let mut a2 = (a.0.div_euclid(2), a.1.div_euclid(2));
+    let mut b2 = (b.0.div_euclid(2), b.1.div_euclid(2));
+    let (w2, h2) = ((a2.0 + a2.1).abs(), (b2.0 + b2.1).abs());
+
+    if 2 * w > 3 * h {
+        // Long rectangle, so split it in two along the major axis
+        if w2 % 2 == 1 && w > 2 {
+            a2 = (a2.0 + da.0, a2.1 + da.1);
+        }
+
+        gilbert_step(out, (x, y), a2, b);
+        gilbert_step(out, (x + a2.0, y + a2.1), (a.0 - a2.0, a.1 - a2.1), b);
+    } else {
+        // Otherwise go up along the minor axis, across, then back down
+        if h2 % 2 == 1 && h > 2 {
+            b2 = (b2.0 + db.0, b2.1 + db.1);
+        }
+
+        gilbert_step(out, (x, y), b2, a2);
+        gilbert_step(out, (x + b2.0, y + b2.1), a, (b.0 - b2.0, b.1 - b2.1));
+        gilbert_step(
+            out,
+            (x + (a.0 - da.0) + (b2.0 - db.0), y + (a.1 - da.1) + (b2.1 - db.1)),
+            (-b2.0, -b2.1),
+            (-(a.0 - a2.0), -(a.1 - a2.1)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid where each cell holds its own (row, col), with the bottom row first
+    fn grid(rows: u8, cols: u8) -> KeyGrid {
+        (0..rows).map(|row| (0..cols).map(|col| vec![(row, col)]).collect()).collect()
+    }
+
+    /// Arrange a grid into bars, where the layout isn't used
+    fn bars(orientation: Orientation, grid: &KeyGrid) -> KeyGrid {
+        orientation.arrange(grid, &KeyLayout::grid(0, 0), |_| true)
+    }
+
+    /// Arrange every key of a layout into single key bands
+    fn per_key(orientation: Orientation, layout: &KeyLayout, include: impl Fn(&PlacedKey) -> bool) -> Vec<(u8, u8)> {
+        let bars = orientation.arrange(&layout.columns(0), layout, include);
+        assert_eq!(bars.len(), 1);
+        bars[0].iter().map(|band| band[0]).collect()
+    }
+
+    #[test]
+    fn standard() {
+        assert_eq!(bars(Orientation::Standard, &grid(2, 3)), grid(2, 3))
+    }
+
+    #[test]
+    fn reversed() {
+        let bars = bars(Orientation::Reversed, &grid(2, 3));
+        assert_eq!(bars[0][0], vec![(0, 2)]);
+        assert_eq!(bars[1][2], vec![(1, 0)])
+    }
+
+    #[test]
+    fn mirrored_even() {
+        let bars = bars(Orientation::Mirrored, &grid(1, 4));
+        assert_eq!(bars[0], vec![vec![(0, 1), (0, 2)], vec![(0, 0), (0, 3)]])
+    }
+
+    #[test]
+    fn mirrored_odd() {
+        let bars = bars(Orientation::Mirrored, &grid(1, 5));
+        assert_eq!(bars[0], vec![vec![(0, 2)], vec![(0, 1), (0, 3)], vec![(0, 0), (0, 4)]])
+    }
+
+    #[test]
+    fn hanging() {
+        let bars = bars(Orientation::Hanging, &grid(3, 1));
+        assert_eq!(bars, vec![vec![vec![(2, 0)]], vec![vec![(1, 0)]], vec![vec![(0, 0)]]])
+    }
+
+    #[test]
+    fn snake_order() {
+        // Matrix rows count from the top, so the bottom row is row 1
+        let keys = per_key(Orientation::Snake, &KeyLayout::grid(2, 3), |_| true);
+        assert_eq!(keys, vec![(1, 0), (1, 1), (1, 2), (0, 2), (0, 1), (0, 0)])
+    }
+
+    #[test]
+    fn per_key_skips_excluded() {
+        let keys = per_key(Orientation::Snake, &KeyLayout::grid(2, 2), |k| k.matrix != (1, 1));
+        assert_eq!(keys, vec![(1, 0), (0, 1), (0, 0)])
+    }
+
+    #[test]
+    fn per_key_every_key() {
+        // Every key gets its own band, including wide keys that share a spectrum column with another
+        let layout = KeyLayout::from_def(&crate::LAYOUTS[0], crate::Physical::Iso);
+        for orientation in [Orientation::Snake, Orientation::Hilbert] {
+            let mut keys = per_key(orientation, &layout, |_| true);
+            assert_eq!(keys.len(), layout.keys().count());
+            keys.sort();
+            keys.dedup();
+            assert_eq!(keys.len(), layout.keys().count(), "{orientation}")
+        }
+    }
+
+    #[test]
+    fn hilbert_visits_all_neighbours() {
+        for (cols, rows) in [(21, 6), (14, 5), (17, 6), (3, 1), (4, 4), (5, 8)] {
+            let order = gilbert(cols, rows);
+
+            let mut sorted = order.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), cols * rows, "{cols}x{rows}");
+            assert!(order.iter().all(|(c, r)| *c < cols && *r < rows), "{cols}x{rows}");
+
+            // Each step only moves to an adjacent key, apart from the odd diagonal on some sizes
+            assert!(
+                order.windows(2).all(|w| w[0].0.abs_diff(w[1].0) <= 1 && w[0].1.abs_diff(w[1].1) <= 1),
+                "{cols}x{rows}"
+            );
+        }
+    }
+
+    #[test]
+    fn horizontal() {
+        let bars = bars(Orientation::Horizontal, &grid(2, 3));
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars[2], vec![vec![(0, 2)], vec![(1, 2)]])
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{Rgb, Rgba};
+
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, EnumString};
+use wooting_rgb::Key;
+
+const WHITE: Rgb = Rgb(255, 255, 255);
+
+/// A lock key whose state can be shown on top of the spectrum
+#[derive(
+    Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Display, EnumString, AsRefStr, EnumIter,
+)]
+pub enum Indicator {
+    #[strum(to_string = "Caps Lock")]
+    CapsLock,
+    #[strum(to_string = "Num Lock")]
+    NumLock,
+    #[strum(to_string = "Scroll Lock")]
+    ScrollLock,
+}
+
+impl Indicator {
+    /// Whether the lock is currently on
+    pub fn is_on(&self) -> bool {
+        match self {
+            Self::CapsLock => toggle_keys::get_caps_lock_state(),
+            Self::NumLock => toggle_keys::get_num_lock_state(),
+            Self::ScrollLock => toggle_keys::get_scroll_lock_state(),
+        }
+    }
+
+    /// The (row, col) matrix cell of the lock key, which is wired the same way on every Wooting keyboard
+    /// Keyboards whose [crate::KeyLayout] doesn't have a key in that cell skip the indicator
+    pub fn matrix(&self) -> (u8, u8) {
+        match self {
+            Self::CapsLock => Key::CapsLock.into(),
+            Self::NumLock => (1, 17),
+            Self::ScrollLock => (0, 15),
+        }
+    }
+}
+
+/// How an overlay's colour is combined with the colour of the key underneath it
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, PartialEq, Display, EnumString, AsRefStr, EnumIter)]
+pub enum BlendMode {
+    /// Ignore the key underneath
+    #[default]
+    Replace,
+    /// Add the channels together, saturating at full brightness
+    Add,
+    /// Multiply the channels, tinting the key underneath
+    Multiply,
+    /// The inverse of multiplying the inverses, which brightens without clipping as harshly as [BlendMode::Add]
+    Screen,
+    /// Halfway between the two colours
+    Average,
+}
+
+impl BlendMode {
+    pub fn blend(&self, below: Rgb, above: Rgb) -> Rgb {
+        match self {
+            Self::Replace => above,
+            Self::Add => below + above,
+            Self::Multiply => below * above,
+            Self::Screen => !(!below * !above),
+            Self::Average => below.interpolate(&above, 0.5),
+        }
+    }
+}
+
+/// A fixed colour drawn over a set of keys
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Overlay {
+    /// (row, col) matrix cells of the keys to colour
+    pub keys: Vec<(u8, u8)>,
+    /// Blended with the key underneath, then layered over it in proportion to the alpha
+    pub colour: Rgba,
+    #[serde(default)]
+    pub blend: BlendMode,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self { keys: Vec::new(), colour: WHITE.into(), blend: BlendMode::default() }
+    }
+}
+
+impl Overlay {
+    /// The colour of a key with the overlay drawn over it
+    pub fn layer(&self, below: Rgb) -> Rgb {
+        Rgba::new(self.blend.blend(below, self.colour.rgb), self.colour.alpha).over(below)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_modes() {
+        let below = Rgb(200, 100, 0);
+        let above = Rgb(100, 255, 0);
+
+        assert_eq!(BlendMode::Replace.blend(below, above), above);
+        assert_eq!(BlendMode::Add.blend(below, above), Rgb(255, 255, 0));
+        assert_eq!(BlendMode::Multiply.blend(below, above), Rgb(78, 100, 0));
+        assert_eq!(BlendMode::Screen.blend(below, above), Rgb(222, 255, 0));
+        assert_eq!(BlendMode::Average.blend(below, above), Rgb(150, 177, 0))
+    }
+
+    #[test]
+    fn layering() {
+        let below = Rgb(200, 100, 0);
+        let overlay = |colour: Rgba, blend| Overlay { keys: vec![], colour, blend };
+
+        assert_eq!(overlay(Rgba::new(WHITE, 0), BlendMode::Replace).layer(below), below);
+        assert_eq!(overlay(Rgba::new(WHITE, 255), BlendMode::Multiply).layer(below), below);
+        assert_eq!(overlay(Rgba::new(Rgb(0, 0, 0), 128), BlendMode::Replace).layer(below), Rgb(100, 50, 0))
+    }
+}
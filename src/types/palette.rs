@@ -0,0 +1,220 @@
+use crate::{ColourSpace, Error, GradientStop, GradientTheme, Result, Rgb};
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// How many colours are picked out of an image
+const PALETTE_SIZE: usize = 5;
+/// Images are shrunk to fit this before their colours are counted, as the palette barely changes
+const SAMPLE_SIZE: u32 = 64;
+/// The most times k-means moves the colours, though it usually settles well before
+const K_MEANS_ROUNDS: usize = 16;
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// Find the dominant colours in a set of pixels
+/// Median cut gives a rough palette, which k-means then moves towards the middle of each cluster of colours
+/// Fewer colours are returned if there aren't enough distinct ones
+pub fn dominant_colours(pixels: Vec<Rgb>, count: usize) -> Vec<Rgb> {
+    let palette = median_cut(pixels.clone(), count);
+    k_means(&pixels, palette)
+}
+
+/// The pixels are repeatedly split in half at the median of whichever group spans the widest range of a channel,
+/// and each group is then averaged
+fn median_cut(pixels: Vec<Rgb>, count: usize) -> Vec<Rgb> {
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    let mut groups = vec![pixels];
+    while groups.len() < count {
+        let widest =
+            groups.iter().enumerate().map(|(i, group)| (i, widest_channel(group))).max_by_key(|(_, (_, range))| *range);
+        let Some((i, (channel, range))) = widest else { break };
+        if range == 0 {
+            break;
+        }
+
+        let mut group = groups.swap_remove(i);
+        group.sort_unstable_by_key(|p| channels(*p)[channel]);
+        let upper = group.split_off(group.len() / 2);
+        groups.extend([group, upper]);
+    }
+
+    groups.iter().map(|group| average(group)).collect()
+}
+
+/// Repeatedly move each colour to the average of the pixels closest to it
+fn k_means(pixels: &[Rgb], mut palette: Vec<Rgb>) -> Vec<Rgb> {
+    for _ in 0..K_MEANS_ROUNDS {
+        let mut groups = vec![Vec::new(); palette.len()];
+        for &pixel in pixels {
+            let nearest = (0..palette.len()).min_by_key(|&i| distance(pixel, palette[i]));
+            if let Some(nearest) = nearest {
+                groups[nearest].push(pixel)
+            }
+        }
+
+        let moved: Vec<_> = groups
+            .iter()
+            .zip(&palette)
+            .map(|(group, &colour)| if group.is_empty() { colour } else { average(group) })
+            .collect();
+        if moved == palette {
+            break;
+        }
+        palette = moved;
+    }
+
+    palette
+}
+
+/// Squared distance between two colours
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    channels(a).into_iter().zip(channels(b)).map(|(a, b)| u32::from(a.abs_diff(b)).pow(2)).sum()
+}
+
+fn channels(rgb: Rgb) -> [u8; 3] {
+    [rgb.0, rgb.1, rgb.2]
+}
+
+/// The channel with the widest range of values in a group of pixels, along with that range
+fn widest_channel(pixels: &[Rgb]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = pixels.iter().map(|p| channels(*p)[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn average(pixels: &[Rgb]) -> Rgb {
+    let len = pixels.len().max(1) as u32;
+    let sum = pixels.iter().fold([0u32; 3], |sum, p| {
+        let [r, g, b] = channels(*p);
+        [sum[0] + u32::from(r), sum[1] + u32::from(g), sum[2] + u32::from(b)]
+    });
+    let channel = |total: u32| ((total + len / 2) / len) as u8;
+    Rgb(channel(sum[0]), channel(sum[1]), channel(sum[2]))
+}
+
+impl GradientTheme {
+    /// A gradient through a palette from its darkest colour on the bottom row to its brightest at the top
+    pub fn from_palette(mut palette: Vec<Rgb>) -> Self {
+        palette.sort_by(|a, b| a.luminance().total_cmp(&b.luminance()));
+        palette.dedup();
+
+        let last = palette.len().saturating_sub(1).max(1) as f32;
+        let stops = palette
+            .into_iter()
+            .enumerate()
+            .map(|(i, colour)| GradientStop { position: i as f32 / last, colour })
+            .collect();
+
+        Self { stops, hue_shift: 0., peak: None, background: Rgb(0, 0, 0), interpolation: ColourSpace::Oklab }
+    }
+
+    /// A gradient through the dominant colours of an image, ignoring transparent pixels
+    pub fn from_image(path: &Path) -> Result<Self> {
+        let mut image = image::open(path).map_err(|e| Error::Image(path.to_path_buf(), e))?;
+        if image.width() > SAMPLE_SIZE || image.height() > SAMPLE_SIZE {
+            image = image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE);
+        }
+
+        let pixels =
+            image.into_rgba8().pixels().filter(|p| p[3] >= u8::MAX / 2).map(|p| Rgb(p[0], p[1], p[2])).collect();
+
+        Ok(Self::from_palette(dominant_colours(pixels, PALETTE_SIZE)))
+    }
+}
+
+/// Create a theme for every image in a directory that doesn't already have one, returning the names of new themes
+/// Existing themes are left alone, so edits to a generated theme aren't lost, and deleting it regenerates it
+pub fn import_images(dir: &Path) -> Vec<Result<String>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(e) => return vec![Err(Error::ThemeIo(dir.to_path_buf(), e))],
+    };
+
+    let mut results = Vec::new();
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()).filter(|name| !name.contains(':')) else {
+            continue;
+        };
+
+        let theme_path = dir.join(format!("{name}.json"));
+        if !is_image || theme_path.exists() {
+            continue;
+        }
+
+        results.push(
+            GradientTheme::from_image(&path).and_then(|theme| theme.write(&theme_path)).map(|_| name.to_string()),
+        );
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    const RED: Rgb = Rgb(255, 0, 0);
+    const BLUE: Rgb = Rgb(0, 0, 255);
+
+    #[test]
+    fn finds_clusters() {
+        let mut pixels = vec![Rgb(250, 0, 0), Rgb(255, 10, 0), Rgb(0, 0, 250), Rgb(10, 0, 255)];
+        pixels.extend([Rgb(255, 5, 0); 10]);
+
+        let mut palette = dominant_colours(pixels, 2);
+        palette.sort_by_key(|p| p.0);
+        assert_eq!(palette, [Rgb(5, 0, 253), Rgb(255, 5, 0)]);
+
+        // Fewer colours are found than asked for when there aren't enough
+        assert_eq!(dominant_colours(vec![RED; 20], 5), [RED]);
+        assert!(dominant_colours(Vec::new(), 5).is_empty())
+    }
+
+    #[test]
+    fn palette_gradient() {
+        let theme = GradientTheme::from_palette(vec![Rgb(255, 255, 255), BLUE, RED, BLUE]);
+        let stops: Vec<_> = theme.stops.iter().map(|stop| (stop.position, stop.colour)).collect();
+        assert_eq!(stops, [(0., BLUE), (0.5, RED), (1., Rgb(255, 255, 255))])
+    }
+
+    #[test]
+    fn imports_images() {
+        let dir = TempDir::new("palette");
+
+        let image =
+            image::RgbImage::from_fn(
+                16,
+                16,
+                |x, _| if x < 8 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) },
+            );
+        image.save(dir.join("Flag.png")).unwrap();
+        std::fs::write(dir.join("Broken.jpg"), "").unwrap();
+
+        let results = import_images(&dir);
+        assert_eq!(results.iter().filter_map(|r| r.as_ref().ok()).collect::<Vec<_>>(), ["Flag"]);
+        assert!(results.iter().any(|r| matches!(r, Err(Error::Image(_, _)))));
+
+        let theme = GradientTheme::read(&dir.join("Flag.json")).unwrap();
+        assert_eq!(theme.stops.iter().map(|stop| stop.colour).collect::<Vec<_>>(), [BLUE, RED]);
+
+        // Themes that already exist aren't overwritten, and nothing is left behind from writing them
+        assert!(import_images(&dir).iter().all(|r| r.is_err()));
+        assert!(!dir.join("Flag.json.tmp").exists());
+
+        assert!(import_images(&dir.join("missing")).is_empty())
+    }
+}
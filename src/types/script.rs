@@ -0,0 +1,327 @@
+use crate::themes::{FrameContext, Theme};
+use crate::{Error, Keyboard, Result, Rgb};
+
+use std::cell::Cell;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT, INT};
+
+/// The function a script defines to colour each key
+const ENTRY_POINT: &str = "colour";
+/// How much work a script can do drawing every key in a frame before it's stopped, so it can't freeze the keyboard
+const FRAME_OPERATIONS: u64 = 500_000;
+/// Scripts run for every key on every frame, so what they print is only logged this often
+const PRINT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_SIZE: usize = 1024;
+
+/// A theme written as a Rhai script in the themes directory, for anything a gradient can't describe
+/// The script defines `fn colour(col, row, height, rows, cols, time, frequency)`, which is called for every key
+/// and returns a colour made with `rgb`, `hsv`, `hsl`, `mix` or a string such as `"#ff8000"`
+#[derive(Debug)]
+pub struct ScriptTheme {
+    /// The script as written, which tells whether a reloaded script has changed
+    source: Arc<str>,
+    /// Compiled once when loaded, and shared between every keyboard and zone using the theme
+    ast: Arc<AST>,
+    /// Set after the script fails, so the error is only logged once rather than for every key
+    failed: AtomicBool,
+    /// Set after the first frame that went over [FRAME_OPERATIONS], so dropped frames are only logged once
+    dropped: AtomicBool,
+    /// Operations done so far this frame, across every key
+    operations: AtomicU64,
+}
+
+thread_local! {
+    /// Operations the script being run can still do, which the engine checks as it goes
+    static REMAINING: Cell<u64> = const { Cell::new(FRAME_OPERATIONS) };
+    /// Operations done by the script being run
+    static DONE: Cell<u64> = const { Cell::new(0) };
+}
+
+impl Clone for ScriptTheme {
+    fn clone(&self) -> Self {
+        Self::new(self.source.clone(), self.ast.clone())
+    }
+}
+
+impl PartialEq for ScriptTheme {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl ScriptTheme {
+    fn new(source: Arc<str>, ast: Arc<AST>) -> Self {
+        Self {
+            source,
+            ast,
+            failed: AtomicBool::new(false),
+            dropped: AtomicBool::new(false),
+            operations: AtomicU64::new(0),
+        }
+    }
+
+    /// Compile a script, trying it out on a key so mistakes are found when it's loaded rather than while drawing
+    pub fn read(path: &Path) -> Result<Self> {
+        let error = |e| Error::Script(path.to_path_buf(), e);
+        let source = std::fs::read_to_string(path).map_err(|e| Error::ThemeIo(path.to_path_buf(), e))?;
+        let ast = engine().compile(&source).map_err(|e| error(e.into()))?;
+        engine().run_ast(&ast).map_err(error)?;
+
+        let theme = Self::new(source.into(), Arc::new(ast));
+        theme.call((0, 0, 1., 6, 21, 0., 440.)).map_err(error)?;
+        theme.operations.store(0, Ordering::Relaxed);
+        Ok(theme)
+    }
+
+    fn call(&self, args: (INT, INT, FLOAT, INT, INT, FLOAT, FLOAT)) -> std::result::Result<Rgb, Box<EvalAltResult>> {
+        // The rest of this frame's budget is handed to the engine, and what's used is taken off it afterwards
+        REMAINING.set(FRAME_OPERATIONS.saturating_sub(self.operations.load(Ordering::Relaxed)));
+        DONE.set(0);
+        // Top level statements are only run when the script is loaded, so every key doesn't pay for them
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = engine().call_fn_with_options(options, &mut Scope::new(), &self.ast, ENTRY_POINT, args);
+        self.operations.fetch_add(DONE.get(), Ordering::Relaxed);
+        REMAINING.set(FRAME_OPERATIONS);
+
+        let colour: Dynamic = result.map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => {
+                format!("{ENTRY_POINT} did more than {FRAME_OPERATIONS} operations in one frame").into()
+            }
+            _ => e,
+        })?;
+
+        if colour.is_string() {
+            let text = colour.into_immutable_string()?;
+            return text.parse().map_err(|e: Error| e.to_string().into());
+        }
+        let type_name = colour.type_name();
+        colour.try_cast().ok_or_else(|| format!("{ENTRY_POINT} returned {type_name} rather than a colour").into())
+    }
+
+    /// Whether the script has used up this frame's budget
+    fn over_budget(&self) -> bool {
+        self.operations.load(Ordering::Relaxed) > FRAME_OPERATIONS
+    }
+}
+
+impl Theme for ScriptTheme {
+    fn update(&mut self, _ctx: &FrameContext) {
+        *self.operations.get_mut() = 0;
+    }
+
+    fn get_led_color(&self, kbd: &Keyboard, col: u8, row: u8, bar_height: f32) -> Rgb {
+        self.get_frame_color(kbd, col, row, bar_height, &FrameContext::new(&Default::default(), Default::default(), 0))
+    }
+
+    fn get_frame_color(&self, kbd: &Keyboard, col: u8, row: u8, bar_height: f32, ctx: &FrameContext) -> Rgb {
+        // A frame that went over budget is dropped, and the script is tried again on the next one
+        if self.failed.load(Ordering::Relaxed) || self.over_budget() {
+            return Rgb(0, 0, 0);
+        }
+
+        let args = (
+            INT::from(col),
+            INT::from(row),
+            FLOAT::from(bar_height),
+            INT::from(kbd.rows()),
+            INT::from(kbd.cols()),
+            ctx.elapsed.as_secs_f64(),
+            FLOAT::from(ctx.band_frequency().unwrap_or(0.)),
+        );
+
+        self.call(args).unwrap_or_else(|e| {
+            if !self.over_budget() {
+                log::warn!("Script theme stopped: {e}");
+                self.failed.store(true, Ordering::Relaxed);
+            } else if !self.dropped.swap(true, Ordering::Relaxed) {
+                log::warn!("Script theme dropped a frame: {e}");
+            }
+            Rgb(0, 0, 0)
+        })
+    }
+}
+
+/// The engine every script runs in, which can't touch files or load other scripts, and limits how much work they do
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_SIZE, MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_SIZE)
+            .set_max_array_size(MAX_SIZE)
+            .set_max_map_size(MAX_SIZE)
+            .on_progress(|operations| {
+                DONE.set(operations);
+                (operations > REMAINING.get()).then_some(Dynamic::UNIT)
+            })
+            .on_print(|text| print(log::Level::Info, text))
+            .on_debug(|text, _, pos| print(log::Level::Debug, &format!("{pos}: {text}")));
+
+        let channel = |c: INT| c.clamp(0, 255) as u8;
+        engine
+            .register_type_with_name::<Rgb>("Colour")
+            .register_fn("rgb", move |r: INT, g: INT, b: INT| Rgb(channel(r), channel(g), channel(b)))
+            .register_fn("hsv", |h: FLOAT, s: FLOAT, v: FLOAT| Rgb::from_hsv(h as f32, s as f32, v as f32))
+            .register_fn("hsl", |h: FLOAT, s: FLOAT, l: FLOAT| Rgb::from_hsl(h as f32, s as f32, l as f32))
+            .register_fn("mix", |a: Rgb, b: Rgb, factor: FLOAT| a.interpolate(&b, factor as f32))
+            .register_get("r", |c: &mut Rgb| INT::from(c.0))
+            .register_get("g", |c: &mut Rgb| INT::from(c.1))
+            .register_get("b", |c: &mut Rgb| INT::from(c.2))
+            .register_fn("to_string", |c: &mut Rgb| c.to_string());
+        engine
+    })
+}
+
+/// Log what a script prints, unless something was logged less than [PRINT_INTERVAL] ago
+fn print(level: log::Level, text: &str) {
+    static LAST: Mutex<Option<Instant>> = Mutex::new(None);
+
+    let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+    if last.map_or(true, |last| last.elapsed() >= PRINT_INTERVAL) {
+        *last = Some(Instant::now());
+        log::log!(level, "{text}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::themes::ThemeChoice;
+    use crate::{KeyLayout, TempDir};
+
+    use wooting_rgb::RgbKeyboard;
+
+    /// Load a script from a temporary file
+    fn script(name: &str, source: &str) -> Result<ScriptTheme> {
+        let dir = TempDir::new("script");
+        let path = dir.join(format!("{name}.rhai"));
+        std::fs::write(&path, source).unwrap();
+        ScriptTheme::read(&path)
+    }
+
+    fn colour(theme: &ScriptTheme, args: (INT, INT, FLOAT, INT, INT, FLOAT, FLOAT)) -> Rgb {
+        theme.call(args).unwrap()
+    }
+
+    #[test]
+    fn colours() {
+        let theme = script(
+            "colours",
+            r##"
+            fn colour(col, row, height, rows, cols, time, frequency) {
+                if row.to_float() >= height { return rgb(0, 0, 0) }
+                switch col {
+                    0 => rgb(300, -5, 128),
+                    1 => hsv(120.0, 1.0, 1.0),
+                    2 => mix(rgb(0, 0, 0), rgb(200, 100, 50), time),
+                    3 => if frequency > 1000.0 { "#ff0000" } else { "#0000ff" },
+                    _ => rgb(rows, cols, 0),
+                }
+            }
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(colour(&theme, (0, 0, 1., 6, 21, 0., 0.)), Rgb(255, 0, 128));
+        assert_eq!(colour(&theme, (1, 0, 1., 6, 21, 0., 0.)), Rgb(0, 255, 0));
+        assert_eq!(colour(&theme, (2, 0, 1., 6, 21, 0.5, 0.)), Rgb(100, 50, 25));
+        assert_eq!(colour(&theme, (3, 0, 1., 6, 21, 0., 2000.)), Rgb(255, 0, 0));
+        assert_eq!(colour(&theme, (3, 0, 1., 6, 21, 0., 100.)), Rgb(0, 0, 255));
+        assert_eq!(colour(&theme, (4, 0, 1., 6, 21, 0., 0.)), Rgb(6, 21, 0));
+        assert_eq!(colour(&theme, (4, 2, 1., 6, 21, 0., 0.)), Rgb(0, 0, 0))
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        let error = |name, source| match script(name, source) {
+            Err(Error::Script(_, e)) => e.to_string(),
+            result => panic!("{result:?}"),
+        };
+
+        // Mistakes are caught when the script is loaded
+        assert!(error("syntax", "fn colour(col, row, height, rows, cols, time, frequency) {").contains("Syntax error"));
+        assert!(error("missing", "fn color(col) { rgb(0, 0, 0) }").contains("Function not found: colour"));
+        let not_colour = error("not_colour", "fn colour(col, row, height, rows, cols, time, frequency) { 42 }");
+        assert!(not_colour.contains("returned i64 rather than a colour"), "{not_colour}");
+        let bad_string = error("bad_string", r#"fn colour(col, row, height, rows, cols, time, frequency) { "blue" }"#);
+        assert!(bad_string.contains("Invalid colour: blue"), "{bad_string}")
+    }
+
+    #[test]
+    fn sandboxed() {
+        let error = |name, source| match script(name, source) {
+            Err(Error::Script(_, e)) => *e,
+            result => panic!("{result:?}"),
+        };
+
+        let endless = error("endless", "fn colour(col, row, height, rows, cols, time, frequency) { loop {} }");
+        assert!(endless.to_string().contains("operations in one frame"), "{endless}");
+
+        let import = error("import", r#"import "other" as other; fn colour(a, b, c, d, e, f, g) { rgb(0, 0, 0) }"#);
+        assert!(matches!(import, EvalAltResult::ErrorModuleNotFound(..)), "{import}");
+
+        let eval =
+            error("eval", r#"fn colour(col, row, height, rows, cols, time, frequency) { eval("rgb(0, 0, 0)") }"#);
+        assert!(matches!(eval, EvalAltResult::ErrorParsing(..)), "{eval}")
+    }
+
+    #[test]
+    fn frame_budget() {
+        let mut theme = script(
+            "slow",
+            "fn colour(col, row, height, rows, cols, time, frequency) { let n = 0; for i in 0..10000 { n += i } rgb(0, 0, 0) }",
+        )
+        .unwrap();
+        let args = (0, 0, 1., 6, 21, 0., 0.);
+
+        // Each key is well within the budget, but a whole keyboard's worth isn't
+        let keys = (0..1000).take_while(|_| theme.call(args).is_ok()).count();
+        assert!((1..1000).contains(&keys), "{keys}");
+        assert!(theme.call(args).unwrap_err().to_string().contains("operations in one frame"));
+
+        let spectrum = Default::default();
+        theme.update(&FrameContext::new(&spectrum, Default::default(), 1));
+        assert!(theme.call(args).is_ok())
+    }
+
+    #[test]
+    fn dropped_frame() {
+        let mut theme = script(
+            "spiky",
+            r#"
+            fn colour(col, row, height, rows, cols, time, frequency) {
+                if time > 2.0 { throw "broken" }
+                if time > 1.0 { let n = 0; for i in 0..1000000 { n += i } }
+                rgb(255, 0, 0)
+            }
+            "#,
+        )
+        .unwrap();
+        let kbd = Keyboard::new(RgbKeyboard, ThemeChoice::default().into(), 0, KeyLayout::grid(6, 21), 21);
+        let spectrum = Default::default();
+        let frame = |theme: &mut ScriptTheme, secs| {
+            let ctx = FrameContext::new(&spectrum, Duration::from_secs_f32(secs), 0);
+            theme.update(&ctx);
+            theme.get_frame_color(&kbd, 0, 0, 1., &ctx)
+        };
+
+        // Going over budget only drops the frame it happened in
+        assert_eq!(frame(&mut theme, 1.5), Rgb(0, 0, 0));
+        assert_eq!(frame(&mut theme, 0.), Rgb(255, 0, 0));
+
+        // But an error stops the script for good
+        assert_eq!(frame(&mut theme, 3.), Rgb(0, 0, 0));
+        assert_eq!(frame(&mut theme, 0.), Rgb(0, 0, 0))
+    }
+}
@@ -1,6 +1,8 @@
 use crate::MIN_DB;
 
 const FLUTTER_DB_THRESHOLD: f32 = 2.;
+/// How much of a beat pulse fades each tick
+const BEAT_FADE: f32 = 0.08;
 
 /// Holds the state of Frequency-DB pairs to be displayed
 /// A stateful counterpart to [crate::FreqIntensity] 
@@ -8,6 +10,10 @@ const FLUTTER_DB_THRESHOLD: f32 = 2.;
 pub struct SpectrumMemory {
     pub weights: Vec<(f32, f32)>,
     pub decay: f32,
+    /// The loudness of each audio channel in DB, which decays in the same way as the weights
+    pub loudness: Vec<f32>,
+    /// Intensity of the most recent beat, from 1 when it's detected down to 0
+    pub beat: f32,
 }
 
 impl SpectrumMemory {
@@ -15,11 +21,7 @@ impl SpectrumMemory {
     /// The frequencies attribute is intended to be generated by [crate::FreqIntensity::stateless_log_sections]
     pub fn new(frequencies: Vec<f32>, decay: f32) -> Self {
         let weights = frequencies.into_iter().map(|x| (x, MIN_DB)).collect();
-        Self {
-            weights,
-            decay,
-            // ..Default::default()
-        }
+        Self { weights, decay, ..Default::default() }
     }
 
     /// Add a new frame of data to the state
@@ -47,6 +49,23 @@ impl SpectrumMemory {
             });
     }
 
+    /// Add the loudness of each channel for a new frame, as generated by [crate::fft::loudness]
+    /// As with [Self::push], a louder value is taken on immediately
+    pub fn push_loudness(&mut self, loudness: Vec<f32>) {
+        if self.loudness.len() != loudness.len() {
+            self.loudness = vec![MIN_DB; loudness.len()];
+        }
+
+        self.loudness.iter_mut().zip(loudness).for_each(|(a, b)| *a = a.max(b));
+    }
+
+    /// Restart the beat pulse if a beat was detected this frame
+    pub fn push_beat(&mut self, beat: bool) {
+        if beat {
+            self.beat = 1.;
+        }
+    }
+
     /// Apply the decay to the intensities of all frequencies
     pub fn tick(&mut self) {
         self.weights = self
@@ -54,6 +73,8 @@ impl SpectrumMemory {
             .iter()
             .map(|x| (x.0, (x.1 - self.decay).max(MIN_DB)))
             .collect();
+        self.loudness = self.loudness.iter().map(|x| (x - self.decay).max(MIN_DB)).collect();
+        self.beat = (self.beat - BEAT_FADE).max(0.);
     }
 }
 
@@ -102,6 +123,24 @@ mod tests {
         assert_eq!(mem, mem2,)
     }
 
+    #[test]
+    fn loudness() {
+        let mut mem = SpectrumMemory::new(vec![440.], 1.);
+        mem.push_loudness(vec![-10., -20.]);
+        mem.tick();
+        mem.push_loudness(vec![-15., -12.]);
+        assert_eq!(mem.loudness, vec![-11., -12.])
+    }
+
+    #[test]
+    fn beat_fades() {
+        let mut mem = SpectrumMemory::new(vec![440.], 1.);
+        mem.push_beat(true);
+        mem.tick();
+        mem.push_beat(false);
+        assert_eq!(mem.beat, 1. - BEAT_FADE)
+    }
+
     #[test]
     fn push() {
         let mut mem = SpectrumMemory::new(vec![440.], 1.);
@@ -1,5 +1,11 @@
-use crate::Keyboard;
-use crate::Rgb;
+use crate::{ColourSpace, Rgb, SpectrumMemory, FREQ_RANGE, MAX_DB, MIN_DB};
+use crate::{Keyboard, UserTheme};
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, Display, EnumIter, EnumString};
@@ -24,6 +30,9 @@ pub enum ThemeChoice {
     Blossom,
     Rainbow,
     Fire,
+    Drift,
+    Spectral,
+    Synesthesia,
 }
 
 impl ThemeChoice {
@@ -36,12 +45,194 @@ impl ThemeChoice {
             ThemeChoice::Blossom => Box::new(BlossomTheme {}),
             ThemeChoice::Rainbow => Box::new(RainbowTheme {}),
             ThemeChoice::Fire => Box::new(FireTheme {}),
+            ThemeChoice::Drift => Box::new(DriftTheme::default()),
+            ThemeChoice::Spectral => Box::new(SpectralTheme {}),
+            ThemeChoice::Synesthesia => Box::new(SynesthesiaTheme {}),
+        }
+    }
+}
+
+/// A theme that can be selected, either built in or loaded from the themes directory
+/// Stored as a plain name, so options saved before user themes existed can still be read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ThemeName {
+    BuiltIn(ThemeChoice),
+    /// The file name of a [UserTheme], without its extension
+    User(String),
+}
+
+impl Default for ThemeName {
+    fn default() -> Self {
+        Self::BuiltIn(ThemeChoice::default())
+    }
+}
+
+impl From<ThemeChoice> for ThemeName {
+    fn from(choice: ThemeChoice) -> Self {
+        Self::BuiltIn(choice)
+    }
+}
+
+impl Display for ThemeName {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::BuiltIn(choice) => write!(f, "{choice}"),
+            Self::User(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Built-in themes take priority over user themes with the same name
+impl FromStr for ThemeName {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ThemeChoice::from_str(s).map_or_else(|_| Self::User(s.to_string()), Self::BuiltIn))
+    }
+}
+
+impl ThemeName {
+    /// Look up the user theme this refers to, if it's one that's been loaded
+    pub fn source(&self, user_themes: &BTreeMap<String, UserTheme>) -> ThemeSource {
+        let user = match self {
+            Self::BuiltIn(_) => None,
+            Self::User(name) => user_themes.get(name).cloned(),
+        };
+        ThemeSource { name: self.clone(), user }
+    }
+}
+
+/// Everything a theme is built from, so reloading can tell whether it has actually changed
+/// Rebuilding a theme that hasn't changed would restart its animation and fade it in again
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemeSource {
+    pub name: ThemeName,
+    /// The user theme the name refers to, as it was loaded
+    pub user: Option<UserTheme>,
+}
+
+impl ThemeSource {
+    /// Falls back on the default theme if a user theme has been removed
+    pub fn get_theme(&self) -> Box<dyn Theme> {
+        match (&self.name, &self.user) {
+            (ThemeName::BuiltIn(choice), _) => choice.get_theme(),
+            (ThemeName::User(_), Some(theme)) => theme.get_theme(),
+            (ThemeName::User(name), None) => {
+                log::warn!("Theme {name} not found");
+                ThemeChoice::default().get_theme()
+            }
         }
     }
 }
 
+impl From<ThemeChoice> for ThemeSource {
+    fn from(choice: ThemeChoice) -> Self {
+        Self { name: choice.into(), user: None }
+    }
+}
+
+/// Everything about the frame being drawn, for themes that animate or react to the music
+#[derive(Debug, Clone, Copy)]
+pub struct FrameContext<'a> {
+    /// Time since drawing started
+    pub elapsed: Duration,
+    /// Number of frames drawn since drawing started
+    pub frame: u64,
+    /// Loudness of the loudest channel, from 0 to 1
+    pub loudness: f32,
+    /// Centre frequency of the band being drawn in Hz, which is only known for keys showing the spectrum
+    pub frequency: Option<f32>,
+    pub spectrum: &'a SpectrumMemory,
+}
+
+impl<'a> FrameContext<'a> {
+    pub fn new(spectrum: &'a SpectrumMemory, elapsed: Duration, frame: u64) -> Self {
+        let db = spectrum.loudness.iter().copied().fold(MIN_DB, f32::max);
+        let loudness = ((db - MIN_DB) / (MAX_DB - MIN_DB)).clamp(0., 1.);
+
+        Self { elapsed, frame, loudness, frequency: None, spectrum }
+    }
+
+    /// The same frame, while drawing the band at a frequency
+    pub fn with_frequency(self, frequency: f32) -> Self {
+        Self { frequency: Some(frequency), ..self }
+    }
+
+    /// The frequency of the band being drawn, or of the loudest band for keys that aren't showing the spectrum
+    pub fn band_frequency(&self) -> Option<f32> {
+        let loudest = || self.spectrum.weights.iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(|(freq, _)| *freq);
+        self.frequency.or_else(loudest)
+    }
+}
+
 pub trait Theme {
     fn get_led_color(&self, kbd: &Keyboard, cur_col: u8, cur_row: u8, bar_height: f32) -> Rgb;
+
+    /// Called once at the start of every frame, before any keys are drawn, so the theme can animate
+    fn update(&mut self, _ctx: &FrameContext) {}
+
+    /// Gets a key's colour knowing about the frame being drawn, which is what keyboards call
+    /// Themes that don't animate only need [Self::get_led_color], which this falls back on
+    fn get_frame_color(&self, kbd: &Keyboard, cur_col: u8, cur_row: u8, bar_height: f32, _ctx: &FrameContext) -> Rgb {
+        self.get_led_color(kbd, cur_col, cur_row, bar_height)
+    }
+}
+
+/// Fades out the previous theme after switching, so the change isn't a hard cut
+pub struct Crossfade {
+    from: Box<dyn Theme>,
+    duration: Duration,
+    /// When the fade started, which is only known once the first frame is drawn
+    start: Option<Duration>,
+    /// How far through the fade it is, from 0 to 1
+    progress: f32,
+}
+
+impl Crossfade {
+    pub fn new(from: Box<dyn Theme>, duration: Duration) -> Self {
+        Self { from, duration, start: None, progress: 0. }
+    }
+
+    /// Move the fade on, keeping the outgoing theme animating while it's visible
+    pub fn update(&mut self, ctx: &FrameContext) {
+        self.from.update(ctx);
+
+        let start = *self.start.get_or_insert(ctx.elapsed);
+        self.progress = if self.duration.is_zero() {
+            1.
+        } else {
+            (ctx.elapsed.saturating_sub(start).as_secs_f32() / self.duration.as_secs_f32()).min(1.)
+        };
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress >= 1.
+    }
+
+    /// A theme that draws the incoming theme blended over the outgoing one
+    pub fn over<'a>(&'a self, to: &'a dyn Theme) -> Blend<'a> {
+        Blend { from: self.from.as_ref(), to, progress: self.progress }
+    }
+}
+
+/// Two themes blended together, as drawn during a [Crossfade]
+pub struct Blend<'a> {
+    from: &'a dyn Theme,
+    to: &'a dyn Theme,
+    progress: f32,
+}
+
+impl Theme for Blend<'_> {
+    fn get_led_color(&self, kbd: &Keyboard, col: u8, row: u8, bar_height: f32) -> Rgb {
+        let from = self.from.get_led_color(kbd, col, row, bar_height);
+        from.interpolate(&self.to.get_led_color(kbd, col, row, bar_height), self.progress)
+    }
+
+    fn get_frame_color(&self, kbd: &Keyboard, col: u8, row: u8, bar_height: f32, ctx: &FrameContext) -> Rgb {
+        let from = self.from.get_frame_color(kbd, col, row, bar_height, ctx);
+        from.interpolate(&self.to.get_frame_color(kbd, col, row, bar_height, ctx), self.progress)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -152,7 +343,8 @@ impl Theme for BlossomTheme {
 
         let top_intensity = get_key_intensity(bar_height, row);
 
-        PINK.interpolate(&LIGHT_PINK, bar_height / kbd.rows() as f32).intensify(top_intensity)
+        // Blended perceptually, as blending the bytes makes the middle look dull
+        PINK.interpolate_in(&LIGHT_PINK, bar_height / kbd.rows() as f32, ColourSpace::Oklab).intensify(top_intensity)
     }
 }
 
@@ -169,6 +361,9 @@ impl Theme for RainbowTheme {
         const PURPLE: Rgb = Rgb::from_hex(0xff00ff);
         const PINK: Rgb = Rgb::from_hex(0xff009b);
 
+        // Blended round the hue wheel, so neighbouring colours don't pass through grey
+        const SPACE: ColourSpace = ColourSpace::Oklch;
+
         let fade = bar_height % 1.;
         let top_intensity = get_key_intensity(bar_height, row);
 
@@ -176,23 +371,23 @@ impl Theme for RainbowTheme {
             match bar_height.floor() as i16 - Into::<i16>::into(row) {
                 i16::MIN..=-1 => BLACK,
                 0 => RED,
-                1 => RED.interpolate(&ORANGE, fade),
-                2 => ORANGE.interpolate(&GREEN, fade),
-                3 => GREEN.interpolate(&BLUE, fade),
-                4 => BLUE.interpolate(&PURPLE, fade),
-                5 => PURPLE.interpolate(&PINK, fade),
+                1 => RED.interpolate_in(&ORANGE, fade, SPACE),
+                2 => ORANGE.interpolate_in(&GREEN, fade, SPACE),
+                3 => GREEN.interpolate_in(&BLUE, fade, SPACE),
+                4 => BLUE.interpolate_in(&PURPLE, fade, SPACE),
+                5 => PURPLE.interpolate_in(&PINK, fade, SPACE),
                 6..=i16::MAX => PINK,
             }
         } else {
             match bar_height.floor() as i16 - Into::<i16>::into(row) {
                 i16::MIN..=-1 => BLACK,
                 0 => RED,
-                1 => RED.interpolate(&ORANGE, fade),
-                2 => ORANGE.interpolate(&YELLOW, fade),
-                3 => YELLOW.interpolate(&GREEN, fade),
-                4 => GREEN.interpolate(&BLUE, fade),
-                5 => BLUE.interpolate(&PURPLE, fade),
-                6 => PURPLE.interpolate(&PINK, fade),
+                1 => RED.interpolate_in(&ORANGE, fade, SPACE),
+                2 => ORANGE.interpolate_in(&YELLOW, fade, SPACE),
+                3 => YELLOW.interpolate_in(&GREEN, fade, SPACE),
+                4 => GREEN.interpolate_in(&BLUE, fade, SPACE),
+                5 => BLUE.interpolate_in(&PURPLE, fade, SPACE),
+                6 => PURPLE.interpolate_in(&PINK, fade, SPACE),
                 7..=i16::MAX => PINK,
             }
         }
@@ -227,3 +422,175 @@ impl Theme for FireTheme {
         .intensify(top_intensity * 2.)
     }
 }
+
+/// A fully saturated colour with the given hue in degrees
+fn hue(degrees: f32) -> Rgb {
+    Rgb::from_hsv(degrees, 1., 1.)
+}
+
+/// A rainbow across the columns that drifts over time, faster the louder the music is
+#[derive(Debug, Default)]
+pub struct DriftTheme {
+    /// Hue of the first column in degrees
+    offset: f32,
+    /// When the hue last drifted, which is unknown until the first frame is drawn
+    last: Option<Duration>,
+}
+
+impl DriftTheme {
+    /// Degrees the hue drifts per second in silence, which is tripled at full volume
+    const SPEED: f32 = 30.;
+    /// Degrees between neighbouring columns
+    const SPREAD: f32 = 15.;
+}
+
+impl Theme for DriftTheme {
+    fn get_led_color(&self, _kbd: &Keyboard, col: u8, row: u8, bar_height: f32) -> Rgb {
+        hue(self.offset + f32::from(col) * Self::SPREAD).intensify(get_key_intensity(bar_height, row))
+    }
+
+    fn update(&mut self, ctx: &FrameContext) {
+        // The elapsed time starts again from zero if drawing restarts
+        let delta = self.last.map_or(0., |last| ctx.elapsed.saturating_sub(last).as_secs_f32());
+        self.last = Some(ctx.elapsed);
+        self.offset = (self.offset + delta * Self::SPEED * (1. + 2. * ctx.loudness)) % 360.;
+    }
+}
+
+/// Colours each band by its frequency, going round the hue wheel from red in the bass to violet in the treble
+#[derive(Debug, Default)]
+pub struct SpectralTheme {}
+
+impl SpectralTheme {
+    /// Hue of the highest frequency, which stops short of going back round to red
+    const MAX_HUE: f32 = 280.;
+
+    /// Spread over log frequency, so each octave gets the same share of the wheel
+    fn hue(freq: f32) -> f32 {
+        let (low, high) = (FREQ_RANGE.start().ln(), FREQ_RANGE.end().ln());
+        ((freq.ln() - low) / (high - low)).clamp(0., 1.) * Self::MAX_HUE
+    }
+}
+
+impl Theme for SpectralTheme {
+    fn get_led_color(&self, _kbd: &Keyboard, _col: u8, row: u8, bar_height: f32) -> Rgb {
+        hue(0.).intensify(get_key_intensity(bar_height, row))
+    }
+
+    fn get_frame_color(&self, _kbd: &Keyboard, _col: u8, row: u8, bar_height: f32, ctx: &FrameContext) -> Rgb {
+        let freq = ctx.band_frequency().unwrap_or(*FREQ_RANGE.start());
+        hue(Self::hue(freq)).intensify(get_key_intensity(bar_height, row))
+    }
+}
+
+/// Colours each band by its nearest musical note, with the circle of fifths going round the hue wheel
+/// Based on Scriabin's colour organ, where C is red, G is orange and D is yellow
+#[derive(Debug, Default)]
+pub struct SynesthesiaTheme {}
+
+impl SynesthesiaTheme {
+    fn hue(freq: f32) -> f32 {
+        // Semitones above C, with A4 tuned to 440Hz
+        let semitones = 12. * (freq / 440.).log2() + 9.;
+        let note = (semitones.round() as i32).rem_euclid(12);
+        // Each fifth is seven semitones, so this steps round the circle of fifths
+        ((note * 7) % 12) as f32 * 30.
+    }
+}
+
+impl Theme for SynesthesiaTheme {
+    fn get_led_color(&self, _kbd: &Keyboard, _col: u8, row: u8, bar_height: f32) -> Rgb {
+        hue(0.).intensify(get_key_intensity(bar_height, row))
+    }
+
+    fn get_frame_color(&self, _kbd: &Keyboard, _col: u8, row: u8, bar_height: f32, ctx: &FrameContext) -> Rgb {
+        let hue_degrees = ctx.band_frequency().map_or(0., Self::hue);
+        hue(hue_degrees).intensify(get_key_intensity(bar_height, row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_names() {
+        // Options saved before user themes still read as built-in themes
+        assert_eq!(serde_json::from_str::<ThemeName>(r#""Fire""#).unwrap(), ThemeChoice::Fire.into());
+        assert_eq!(serde_json::from_str::<ThemeName>(r#""Sunset""#).unwrap(), ThemeName::User("Sunset".into()));
+        assert_eq!(serde_json::to_string(&ThemeName::from(ThemeChoice::Fire)).unwrap(), r#""Fire""#);
+
+        assert_eq!("Grape".parse(), Ok(ThemeName::from(ThemeChoice::Grape)));
+        assert_eq!("Sunset".parse::<ThemeName>().unwrap().to_string(), "Sunset")
+    }
+
+    #[test]
+    fn hues() {
+        assert_eq!(hue(0.), Rgb(255, 0, 0));
+        assert_eq!(hue(120.), Rgb(0, 255, 0));
+        assert_eq!(hue(-120.), Rgb(0, 0, 255));
+        assert_eq!(hue(30.), Rgb(255, 128, 0))
+    }
+
+    #[test]
+    fn spectral_hues() {
+        assert_eq!(SpectralTheme::hue(*FREQ_RANGE.start()), 0.);
+        assert_eq!(SpectralTheme::hue(*FREQ_RANGE.end()), SpectralTheme::MAX_HUE);
+        assert_eq!(SpectralTheme::hue(1.), 0.)
+    }
+
+    #[test]
+    fn note_hues() {
+        // C4, G4, D5 and A4, slightly out of tune
+        assert_eq!(SynesthesiaTheme::hue(261.63), 0.);
+        assert_eq!(SynesthesiaTheme::hue(392.), 30.);
+        assert_eq!(SynesthesiaTheme::hue(587.33), 60.);
+        assert_eq!(SynesthesiaTheme::hue(445.), 90.);
+        // Octaves are the same colour
+        assert_eq!(SynesthesiaTheme::hue(110.), 90.)
+    }
+
+    #[test]
+    fn band_frequency() {
+        let weights = vec![(100., -20.), (1000., -10.), (5000., -30.)];
+        let spectrum = SpectrumMemory { weights, ..Default::default() };
+        let ctx = FrameContext::new(&spectrum, Duration::ZERO, 0);
+        assert_eq!(ctx.band_frequency(), Some(1000.));
+        assert_eq!(ctx.with_frequency(100.).band_frequency(), Some(100.))
+    }
+
+    #[test]
+    fn crossfade_progress() {
+        let spectrum = SpectrumMemory::default();
+        let at = |ms| FrameContext::new(&spectrum, Duration::from_millis(ms), 0);
+
+        let mut fade = Crossfade::new(Box::new(ClassicTheme {}), Duration::from_millis(400));
+        // The fade starts from the first frame drawn, however long drawing has been going
+        fade.update(&at(1000));
+        assert_eq!(fade.progress, 0.);
+        fade.update(&at(1100));
+        assert_eq!(fade.progress, 0.25);
+        assert!(!fade.is_finished());
+        fade.update(&at(1500));
+        assert!(fade.is_finished());
+
+        let mut instant = Crossfade::new(Box::new(ClassicTheme {}), Duration::ZERO);
+        instant.update(&at(0));
+        assert!(instant.is_finished())
+    }
+
+    #[test]
+    fn drift_speeds_up_when_loud() {
+        let quiet = SpectrumMemory { loudness: vec![MIN_DB], ..Default::default() };
+        let loud = SpectrumMemory { loudness: vec![MAX_DB], ..Default::default() };
+
+        let mut theme = DriftTheme::default();
+        // A theme created partway through drawing starts where it is, rather than jumping ahead
+        theme.update(&FrameContext::new(&quiet, Duration::from_secs(10), 600));
+        assert_eq!(theme.offset, 0.);
+        theme.update(&FrameContext::new(&quiet, Duration::from_secs(11), 660));
+        assert_eq!(theme.offset, DriftTheme::SPEED);
+        theme.update(&FrameContext::new(&loud, Duration::from_secs(12), 720));
+        assert_eq!(theme.offset, DriftTheme::SPEED * 4.)
+    }
+}
@@ -0,0 +1,207 @@
+use crate::themes::{Crossfade, FrameContext, Theme, ThemeSource};
+use crate::{KeyLayout, Keyboard, Rgb, MAX_DB, MIN_DB};
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, EnumString};
+
+const BLACK: Rgb = Rgb(0, 0, 0);
+
+/// A region of the keyboard that can show its own visualisation
+#[derive(
+    Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Display, EnumString, AsRefStr, EnumIter,
+)]
+pub enum Zone {
+    #[strum(to_string = "Main Block")]
+    Main,
+    #[strum(to_string = "Function Row")]
+    FunctionRow,
+    Navigation,
+    Numpad,
+}
+
+impl Zone {
+    /// Gets the zone that a key belongs to from its (row, col) matrix cell
+    /// The matrix is laid out the same way on every Wooting keyboard, so this applies to all of them
+    pub fn of(matrix: (u8, u8)) -> Self {
+        match matrix {
+            (0, _) => Self::FunctionRow,
+            (_, 14..=16) => Self::Navigation,
+            (_, 17..) => Self::Numpad,
+            _ => Self::Main,
+        }
+    }
+}
+
+/// What a [Zone] displays
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, PartialEq, Display, EnumString, AsRefStr, EnumIter)]
+pub enum Visualisation {
+    /// Part of the spectrum, which is spread over every zone showing it
+    #[default]
+    Spectrum,
+    /// A stereo meter, with the left and right channels growing outwards from the middle of the zone
+    Loudness,
+    /// Fills the zone from the bottom whenever a beat is detected
+    Pulse,
+    Off,
+}
+
+/// A zone along with what it should display, and the theme to override the keyboard's with
+pub type ZoneSetting = (Zone, Visualisation, Option<ThemeSource>);
+
+/// A key within a [ZoneDisplay]
+struct ZoneKey {
+    matrix: (u8, u8),
+    /// Horizontal position of the key's centre
+    x: f32,
+    /// Row counted from the bottom of the keyboard
+    row: u8,
+}
+
+/// A zone showing something other than the spectrum
+pub struct ZoneDisplay {
+    zone: Zone,
+    visualisation: Visualisation,
+    /// Overrides the keyboard's theme when set, along with what it was built from
+    theme: Option<(ThemeSource, Box<dyn Theme>)>,
+    /// Only used while the zone's previous theme is fading out
+    fade: Option<Crossfade>,
+    keys: Vec<ZoneKey>,
+}
+
+impl ZoneDisplay {
+    pub fn new(zone: Zone, visualisation: Visualisation, theme: Option<ThemeSource>, layout: &KeyLayout) -> Self {
+        let keys = layout
+            .keys_with_rows()
+            .filter(|(_, k)| Zone::of(k.matrix) == zone)
+            .map(|(row, k)| ZoneKey { matrix: k.matrix, x: k.centre(), row })
+            .collect();
+        let theme = theme.map(|source| {
+            let theme = source.get_theme();
+            (source, theme)
+        });
+
+        Self { zone, visualisation, theme, fade: None, keys }
+    }
+
+    /// Whether this is the given zone showing the given visualisation
+    pub fn shows(&self, zone: Zone, visualisation: Visualisation) -> bool {
+        self.zone == zone && self.visualisation == visualisation
+    }
+
+    /// Switch the zone's own theme, fading from what it showed before, which may have been the keyboard's theme
+    /// Nothing happens if the theme hasn't changed
+    pub fn set_theme(&mut self, source: Option<ThemeSource>, crossfade: Duration, keyboard_theme: &ThemeSource) {
+        if self.theme.as_ref().map(|(source, _)| source) == source.as_ref() {
+            return;
+        }
+
+        let previous = match self.theme.take() {
+            Some((_, theme)) => theme,
+            None => keyboard_theme.get_theme(),
+        };
+        self.theme = source.map(|source| {
+            let theme = source.get_theme();
+            (source, theme)
+        });
+        self.fade = (!crossfade.is_zero()).then(|| Crossfade::new(previous, crossfade));
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Whether the zone includes the key wired to a matrix cell
+    pub fn contains(&self, matrix: (u8, u8)) -> bool {
+        self.keys.iter().any(|k| k.matrix == matrix)
+    }
+
+    /// Let the zone's own theme animate, as the keyboard's theme is updated by the keyboard
+    /// A fade that finished on the last frame is dropped
+    pub fn update(&mut self, ctx: &FrameContext) {
+        if self.fade.as_ref().is_some_and(Crossfade::is_finished) {
+            self.fade = None;
+        }
+
+        if let Some((_, theme)) = self.theme.as_mut() {
+            theme.update(ctx)
+        }
+        if let Some(fade) = self.fade.as_mut() {
+            fade.update(ctx)
+        }
+    }
+
+    /// Calculate the colour of every key in the zone, using the keyboard's theme unless the zone has its own
+    pub fn draw(&self, kbd: &Keyboard, theme: &dyn Theme, ctx: &FrameContext) -> Vec<((u8, u8), Rgb)> {
+        let theme = self.theme.as_ref().map_or(theme, |(_, theme)| theme.as_ref());
+        match self.fade.as_ref() {
+            Some(fade) => self.draw_with(kbd, &fade.over(theme), ctx),
+            None => self.draw_with(kbd, theme, ctx),
+        }
+    }
+
+    /// Bars are scaled to the height of the keyboard, so themes colour them the same way as the spectrum
+    fn draw_with(&self, kbd: &Keyboard, theme: &dyn Theme, ctx: &FrameContext) -> Vec<((u8, u8), Rgb)> {
+        let spec_mem = ctx.spectrum;
+        let rows = kbd.rows() as f32;
+
+        match self.visualisation {
+            Visualisation::Spectrum | Visualisation::Off => self.keys.iter().map(|k| (k.matrix, BLACK)).collect(),
+            Visualisation::Loudness => {
+                let mid = self.span(|k| k.x, |a, b| (a + b) / 2.);
+                let reach = self.span(|k| k.x, |a, b| (b - a) / 2.).max(f32::EPSILON);
+                let level = |channel: usize| {
+                    let db = spec_mem.loudness.get(channel).or(spec_mem.loudness.first()).copied().unwrap_or(MIN_DB);
+                    (db - MIN_DB) / (MAX_DB - MIN_DB) * rows
+                };
+
+                self.keys
+                    .iter()
+                    .map(|k| {
+                        let channel = usize::from(k.x > mid);
+                        let row = ((k.x - mid).abs() / reach * (rows - 1.)).round() as u8;
+                        (k.matrix, theme.get_frame_color(kbd, channel as u8, row, level(channel), ctx))
+                    })
+                    .collect()
+            }
+            Visualisation::Pulse => {
+                let bottom = self.span(|k| k.row as f32, |a, _| a);
+                let height = self.span(|k| k.row as f32, |a, b| b - a).max(1.);
+
+                self.keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, k)| {
+                        let row = ((k.row as f32 - bottom) / height * (rows - 1.)).round() as u8;
+                        (k.matrix, theme.get_frame_color(kbd, i as u8, row, spec_mem.beat * rows, ctx))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Combine the smallest and largest values of a key attribute
+    fn span(&self, attr: impl Fn(&ZoneKey) -> f32, combine: impl Fn(f32, f32) -> f32) -> f32 {
+        let min = self.keys.iter().map(&attr).fold(f32::INFINITY, f32::min);
+        let max = self.keys.iter().map(&attr).fold(f32::NEG_INFINITY, f32::max);
+        if self.keys.is_empty() {
+            0.
+        } else {
+            combine(min, max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zone_of() {
+        assert_eq!(Zone::of((0, 3)), Zone::FunctionRow);
+        assert_eq!(Zone::of((5, 0)), Zone::Main);
+        assert_eq!(Zone::of((4, 15)), Zone::Navigation);
+        assert_eq!(Zone::of((2, 20)), Zone::Numpad)
+    }
+}
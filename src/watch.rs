@@ -0,0 +1,187 @@
+use crate::logging;
+use crate::options::Options;
+use crate::tray::{self, TrayMessage};
+use crate::Result;
+
+use std::collections::BTreeMap;
+use std::fs::{metadata, read_dir};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+/// How often files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the app last wrote the options file, so the watcher can tell its own saves apart from the user's
+static SAVED: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+/// Reload the options and user themes whenever their files change, so palettes can be designed while the app runs
+/// Files are polled rather than watched, as editors save in too many different ways for events to be reliable
+pub fn spawn_watcher(opt: Arc<RwLock<Options>>, tx: SyncSender<TrayMessage>) {
+    spawn(move || {
+        let options_path = Options::get_options_path();
+        let themes_path = Options::get_themes_path();
+
+        let mut options_modified = Settled::new(modified(&options_path));
+        let mut themes_modified = Settled::new(theme_times(&themes_path));
+
+        loop {
+            sleep(POLL_INTERVAL);
+
+            let mut message = None;
+
+            // A deleted options file is ignored, rather than resetting everything
+            if options_modified.update(modified(&options_path))
+                && options_modified.value().is_some()
+                && !saved_by_app(*options_modified.value())
+            {
+                message = reload_options(&opt);
+            }
+
+            if themes_modified.update(theme_times(&themes_path)) {
+                reload_themes(&opt);
+                message = message.or(Some(TrayMessage::ThemeReload));
+            }
+
+            if let Some(message) = message {
+                tray::redraw();
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Swap in the options from the file, keeping the current ones if it can't be read
+fn reload_options(opt: &RwLock<Options>) -> Option<TrayMessage> {
+    log::info!("Reloading options");
+
+    let mut new = match Options::read_from_file() {
+        Ok(new) => new,
+        Err(e) => {
+            log::warn!("{e}");
+            tray::notify(&e);
+            return None;
+        }
+    };
+
+    let mut opt = opt.write().unwrap();
+    // Neither are saved, so they're carried over
+    new.set_connected_keyboards(opt.connected_keyboards.clone());
+    new.user_themes = std::mem::take(&mut opt.user_themes);
+
+    let restart = opt.needs_restart(&new);
+    *opt = new;
+    logging::set_verbosity(opt.verbosity);
+
+    Some(if restart { TrayMessage::Refresh } else { TrayMessage::ThemeReload })
+}
+
+/// Write the options to file without the watcher reloading them, so changes made in the tray are kept
+/// if the file is then edited by hand
+pub fn save_options(opt: &Options) -> Result<()> {
+    save_to(opt, &Options::get_options_path())
+}
+
+fn save_to(opt: &Options, path: &Path) -> Result<()> {
+    // Held while writing, so the watcher can't check the time of a save that's still in progress
+    let mut saved = SAVED.lock().unwrap_or_else(|e| e.into_inner());
+    opt.write_to(path)?;
+    *saved = modified(path);
+    Ok(())
+}
+
+/// Whether the options file is as the app last saved it, rather than changed by the user
+fn saved_by_app(modified: Option<SystemTime>) -> bool {
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) == modified
+}
+
+fn reload_themes(opt: &RwLock<Options>) {
+    log::info!("Reloading themes");
+
+    for e in opt.write().unwrap().load_user_themes() {
+        log::warn!("{e}");
+        tray::notify(&e);
+    }
+}
+
+/// Tracks when files were changed, only reporting a change once it's been the same for a whole poll
+/// Some editors save in several writes, so reading a file as soon as it changes can catch it half written
+struct Settled<T> {
+    /// The value when a change was last reported
+    reported: T,
+    /// The value at the last poll
+    polled: T,
+}
+
+impl<T: Clone + PartialEq> Settled<T> {
+    fn new(value: T) -> Self {
+        Self { reported: value.clone(), polled: value }
+    }
+
+    /// Record the latest value, returning whether it's a change that has now settled
+    fn update(&mut self, value: T) -> bool {
+        let settled = value == self.polled && value != self.reported;
+        if settled {
+            self.reported = value.clone();
+        }
+        self.polled = value;
+        settled
+    }
+
+    fn value(&self) -> &T {
+        &self.reported
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// When each theme file was last changed, so added and removed files are noticed too
+fn theme_times(dir: &Path) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    let Ok(entries) = read_dir(dir) else { return BTreeMap::new() };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .map(|path| (path.clone(), modified(&path)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[test]
+    fn settles() {
+        let mut modified = Settled::new(0);
+        assert!(!modified.update(0));
+
+        // Still being saved
+        assert!(!modified.update(1));
+        assert!(!modified.update(2));
+
+        assert!(modified.update(2));
+        assert_eq!(*modified.value(), 2);
+        assert!(!modified.update(2))
+    }
+
+    #[test]
+    fn own_saves_ignored() {
+        let dir = TempDir::new("watch");
+        let path = dir.join("options.json");
+
+        save_to(&Options::default(), &path).unwrap();
+        assert!(saved_by_app(modified(&path)));
+
+        // Edited by hand afterwards
+        sleep(Duration::from_millis(100));
+        std::fs::write(&path, "{}").unwrap();
+        assert!(!saved_by_app(modified(&path)))
+    }
+}